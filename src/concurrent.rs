@@ -0,0 +1,301 @@
+use crate::table::{Referrer, Undo};
+use crate::{Identity, IndexHandle, MultiBTreeIndex, OnRemove, Shared, Snapshot, Table};
+use crate::{TableError, View};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak};
+
+/// Serializes changes to the foreign keys between tables, so cycles cannot sneak in.
+static FOREIGN_KEYS: Mutex<()> = Mutex::new(());
+
+/// Index of the elements of a table by the primary key of the element they reference.
+type ReferenceIndex<T, P> = MultiBTreeIndex<
+    T,
+    <P as Identity>::PrimaryKey,
+    Box<dyn Fn(&T) -> Option<<P as Identity>::PrimaryKey> + Send + Sync>,
+    Option<<P as Identity>::PrimaryKey>,
+>;
+
+/// Tables referenced through the foreign keys of a concurrent table, by foreign key name.
+///
+/// Kept next to the table rather than in it, so cycles can be detected without locking tables.
+#[derive(Default)]
+struct References(Mutex<BTreeMap<String, Weak<References>>>);
+
+impl References {
+    /// Determine if `target` is this table, or is referenced by it directly or through other
+    /// tables.
+    fn reaches(self: &Arc<Self>, target: &Arc<References>) -> bool {
+        let mut pending = vec![self.clone()];
+        let mut visited = BTreeSet::new();
+        while let Some(table) = pending.pop() {
+            if Arc::ptr_eq(&table, target) {
+                return true;
+            }
+            if visited.insert(Arc::as_ptr(&table)) {
+                let parents = table.0.lock().unwrap_or_else(PoisonError::into_inner);
+                pending.extend(parents.values().filter_map(Weak::upgrade));
+            }
+        }
+        false
+    }
+}
+
+/// Table that can be shared between threads.
+///
+/// Wraps a [`Shared`] table, whose hooks, constraints and watchers are all `Send + Sync`.
+/// Cloning a concurrent table gives another handle to the same table. Any number of threads can
+/// read it at the same time, including looking up indices, while writes are serialized and wait
+/// for all readers to finish.
+///
+/// If a thread panics while writing, the table may have been left half-changed, so the lock is
+/// poisoned and every later access panics as well.
+pub struct ConcurrentTable<T: Identity> {
+    table: Arc<RwLock<Table<T, Shared>>>,
+    references: Arc<References>,
+}
+
+impl<T: Identity> ConcurrentTable<T> {
+    /// Share a table between threads.
+    pub fn new(table: Table<T, Shared>) -> Self {
+        ConcurrentTable {
+            table: Arc::new(RwLock::new(table)),
+            references: Default::default(),
+        }
+    }
+
+    /// Identifies this table among the tables referencing another one.
+    fn id(&self) -> usize {
+        Arc::as_ptr(&self.table) as usize
+    }
+
+    /// Lock the table for reading, waiting for any writer to finish.
+    pub fn read(&self) -> RwLockReadGuard<'_, Table<T, Shared>> {
+        self.table
+            .read()
+            .expect("table poisoned by a panicking writer")
+    }
+
+    /// Lock the table for writing, waiting for all readers and writers to finish.
+    pub fn write(&self) -> RwLockWriteGuard<'_, Table<T, Shared>> {
+        self.table
+            .write()
+            .expect("table poisoned by a panicking writer")
+    }
+}
+
+impl<T: Identity + Clone> ConcurrentTable<T> {
+    /// Look up an element by its primary key, returning a copy of it.
+    pub fn lookup(&self, key: &T::PrimaryKey) -> Option<T> {
+        self.read().lookup(key).cloned()
+    }
+
+    /// Take a snapshot of the table, see [`Table::snapshot`].
+    ///
+    /// The read lock is only held while taking the snapshot, not while reading it.
+    pub fn snapshot(&self) -> Snapshot<T, Shared> {
+        self.read().snapshot()
+    }
+
+    /// Run a closure as a transaction while holding the write lock, see [`Table::transaction`].
+    pub fn transaction<R, E>(
+        &self,
+        transaction: impl FnOnce(&mut Table<T, Shared>) -> Result<R, E>,
+    ) -> Result<R, E> {
+        self.write().transaction(transaction)
+    }
+}
+
+impl<T> ConcurrentTable<T>
+where
+    T: Identity + Clone + Send + Sync + 'static,
+    T::PrimaryKey: Send + Sync,
+{
+    /// Add a foreign key, declaring that elements of this table reference elements of `parent`.
+    ///
+    /// The `reference` returns the primary key of the referenced element, or nothing if the
+    /// element references nothing. Inserting or updating an element whose reference is missing
+    /// from `parent` fails with [`TableError::ForeignKey`], and so does adding the foreign key if
+    /// existing elements have missing references. When referenced elements are removed from
+    /// `parent`, `on_remove` decides what happens to the elements referencing them. If removal
+    /// is restricted, or referencing elements cannot be removed or updated, removing from
+    /// `parent` fails with [`TableError::Referenced`], and the changes other foreign keys made
+    /// for it are undone.
+    ///
+    /// References are checked against the elements last committed to `parent`, without locking
+    /// it, while removing from `parent` locks this table. A table therefore cannot reference
+    /// itself, directly or through other tables, adding such a foreign key fails with
+    /// [`TableError::ForeignKeyCycle`]. Names of foreign keys are unique per table, adding one
+    /// with a name already in use fails with [`TableError::ForeignKeyExists`]. Changes made to
+    /// this table could not be undone along with a transaction on `parent`, so inside one,
+    /// cascading and set-null foreign keys restrict removal instead.
+    ///
+    /// Elements are indexed by the primary key they reference, in a [`MultiBTreeIndex`] under
+    /// the name of the foreign key, so adding it fails with [`TableError::IndexExists`] if an
+    /// index with that name exists.
+    pub fn foreign_key_add<P>(
+        &self,
+        name: &str,
+        parent: &ConcurrentTable<P>,
+        reference: impl Fn(&T) -> Option<P::PrimaryKey> + Send + Sync + 'static,
+        on_remove: OnRemove<T>,
+    ) -> Result<(), TableError<T>>
+    where
+        P: Identity + Clone + Send + Sync + 'static,
+        P::PrimaryKey: Send + Sync,
+    {
+        let _foreign_keys = FOREIGN_KEYS.lock().unwrap_or_else(PoisonError::into_inner);
+        if parent.references.reaches(&self.references) {
+            return Err(TableError::ForeignKeyCycle(name.to_string()));
+        }
+
+        // the parent is locked first, like when removing from it.
+        let mut parent_table = parent.write();
+        let mut table = self.write();
+        if table.foreign_key_exists(name) {
+            return Err(TableError::ForeignKeyExists(name.to_string()));
+        }
+        if table.index(name).is_ok() {
+            return Err(TableError::IndexExists(name.to_string()));
+        }
+
+        // make sure existing data does not reference missing elements
+        let reference = Arc::new(reference);
+        let exists = {
+            let published = parent_table.published();
+            let reference = reference.clone();
+            move |element: &T| match reference(element) {
+                Some(key) => published
+                    .read()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .contains_key(&key),
+                None => true,
+            }
+        };
+        if !table.iter().all(&exists) {
+            return Err(TableError::ForeignKey(name.to_string()));
+        }
+        let index: ReferenceIndex<T, P> = {
+            let reference = reference.clone();
+            MultiBTreeIndex::new(Box::new(move |element: &T| reference(element)))
+        };
+        let by_reference = table.index_add(name, index)?;
+        table.foreign_key_check_add(name, Box::new(exists));
+
+        // the parent only holds on to this table weakly, so they do not keep each other alive.
+        let child = Arc::downgrade(&self.table);
+        let action = move |removed: &BTreeSet<P::PrimaryKey>, restrict: bool| {
+            let Some(table) = child.upgrade() else {
+                return Ok(None);
+            };
+            let mut table = table.write().expect("table poisoned by a panicking writer");
+
+            // the index may have been replaced or removed since, then the elements are scanned.
+            let referencing: Vec<(T::PrimaryKey, P::PrimaryKey)> = match by_reference.index(&table)
+            {
+                Ok(index) => removed
+                    .iter()
+                    .flat_map(|key| index.lookup(key).map(move |element| (element, key.clone())))
+                    .collect(),
+                Err(_) => table
+                    .iter()
+                    .filter_map(|element| {
+                        let key = reference(element).filter(|key| removed.contains(key))?;
+                        Some((element.primary_key(), key))
+                    })
+                    .collect(),
+            };
+            let Some((_, referenced)) = referencing.first() else {
+                return Ok(None);
+            };
+
+            // the changes can be undone if a later foreign key of the parent refuses.
+            let result = match on_remove {
+                // removed all at once, so foreign keys referencing this table are checked before
+                // anything changes.
+                OnRemove::Cascade if !restrict => table.transaction_undoable(|table| {
+                    let keys = referencing.iter().map(|(key, _)| key.clone()).collect();
+                    table.try_remove_keys(&keys)
+                }),
+                OnRemove::SetNull(clear) if !restrict => table.transaction_undoable(|table| {
+                    for (key, _) in &referencing {
+                        table.update(key, clear)?;
+                    }
+                    Ok(())
+                }),
+                _ => return Err(referenced.clone()),
+            };
+            let (_, rollback) = result.map_err(|_| referenced.clone())?;
+            let child = child.clone();
+            let undo = move || {
+                if let Some(table) = child.upgrade() {
+                    let mut table = table.write().unwrap_or_else(PoisonError::into_inner);
+                    table.rollback(rollback);
+                }
+            };
+            Ok(Some(Box::new(undo) as Undo))
+        };
+        let referrer = Referrer {
+            restrict: matches!(on_remove, OnRemove::Restrict),
+            action: Box::new(action),
+        };
+        parent_table.referrer_add((self.id(), name.to_string()), referrer);
+        self.references
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name.to_string(), Arc::downgrade(&parent.references));
+        Ok(())
+    }
+
+    /// Remove a foreign key referencing elements of `parent`, along with its index.
+    ///
+    /// Does nothing if this table has no foreign key with this name referencing `parent`.
+    pub fn foreign_key_remove<P>(&self, name: &str, parent: &ConcurrentTable<P>)
+    where
+        P: Identity + Send + Sync + 'static,
+        P::PrimaryKey: Send + Sync,
+    {
+        let _foreign_keys = FOREIGN_KEYS.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut references = self
+            .references
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        match references.get(name) {
+            Some(referenced) if Weak::ptr_eq(referenced, &Arc::downgrade(&parent.references)) => {}
+            _ => return,
+        }
+        references.remove(name);
+        drop(references);
+
+        let mut parent_table = parent.write();
+        let mut table = self.write();
+        parent_table.referrer_remove(&(self.id(), name.to_string()));
+        let by_reference = IndexHandle::<T, P::PrimaryKey, ReferenceIndex<T, P>>::new(name);
+        if by_reference.index(&*table).is_ok() {
+            table.index_remove(name);
+        }
+        table.foreign_key_check_remove(name);
+    }
+}
+
+impl<T: Identity> Clone for ConcurrentTable<T> {
+    fn clone(&self) -> Self {
+        ConcurrentTable {
+            table: self.table.clone(),
+            references: self.references.clone(),
+        }
+    }
+}
+
+impl<T: Identity> Default for ConcurrentTable<T> {
+    fn default() -> Self {
+        ConcurrentTable::new(Table::default())
+    }
+}
+
+impl<T: Identity> From<Table<T, Shared>> for ConcurrentTable<T> {
+    fn from(table: Table<T, Shared>) -> Self {
+        ConcurrentTable::new(table)
+    }
+}
@@ -5,11 +5,45 @@ use std::error::Error;
 #[derive(thiserror::Error, Debug)]
 pub enum TableError<T: Identity> {
     #[error("Constraint {0:} failed: {1:}")]
-    Constraint(String, Box<dyn Error>),
+    Constraint(String, Box<dyn Error + Send + Sync>),
+    #[error("Hook {0:} failed: {1:}")]
+    Hook(String, Box<dyn Error + Send + Sync>),
     #[error("Value with primary key {0:?} already exists")]
     Exists(T::PrimaryKey),
+    #[error("Value with primary key {0:?} does not exist")]
+    NotFound(T::PrimaryKey),
     #[error("Duplicate entry in index {0:}, already has {1:?}")]
     Duplicate(String, T::PrimaryKey),
+    #[error("Index {0:} does not exist")]
+    UnknownIndex(String),
+    #[error("Index {0:} already exists")]
+    IndexExists(String),
+    #[error("Wrong key type for index {0:}")]
+    KeyType(String),
+    #[error("Index {0:} does not support this operation")]
+    Unsupported(String),
+    #[error("Key of index {0:} is not valid")]
+    InvalidKey(String),
+    #[error("Foreign key {0:} references a missing element")]
+    ForeignKey(String),
+    #[error("Value with primary key {1:?} is still referenced through foreign key {0:}")]
+    Referenced(String, T::PrimaryKey),
+    #[error("Foreign key {0:} already exists")]
+    ForeignKeyExists(String),
+    #[error("Foreign key {0:} would make a table reference itself")]
+    ForeignKeyCycle(String),
+}
+
+impl<T: Identity> TableError<T> {
+    /// Attribute an error reported by an index to the index with the given name.
+    pub(crate) fn index(name: &str, error: IndexError<T>) -> Self {
+        match error {
+            IndexError::Duplicate(key) => TableError::Duplicate(name.to_string(), key),
+            IndexError::KeyType => TableError::KeyType(name.to_string()),
+            IndexError::Unsupported => TableError::Unsupported(name.to_string()),
+            IndexError::InvalidKey => TableError::InvalidKey(name.to_string()),
+        }
+    }
 }
 
 /// Errors that can occur when dealing with indices.
@@ -19,4 +53,20 @@ pub enum IndexError<T: Identity> {
     Duplicate(T::PrimaryKey),
     #[error("Wrong key type")]
     KeyType,
+    #[error("Operation not supported by this index")]
+    Unsupported,
+    #[error("Key is not valid for this index")]
+    InvalidKey,
+}
+
+/// Errors that can occur when dealing with durable tables.
+#[cfg(feature = "wal")]
+#[derive(thiserror::Error, Debug)]
+pub enum WalError<T: Identity> {
+    #[error(transparent)]
+    Table(#[from] TableError<T>),
+    #[error("I/O error: {0:}")]
+    Io(#[from] std::io::Error),
+    #[error("Encoding error: {0:}")]
+    Encoding(#[from] bincode::Error),
 }
@@ -1,17 +1,44 @@
 use crate::Identity;
 use crate::IndexError;
 use std::any::Any;
-use std::collections::btree_map::Entry;
-use std::collections::*;
+use std::ops::Bound;
+
+mod handle;
 
 mod btree;
+mod btree_composite;
+mod btree_multi;
+mod btree_multi_unique;
 mod btree_unique;
 mod hash;
+mod hash_unique;
+mod partial;
+mod prefix;
+#[cfg(feature = "rtree")]
+mod rtree;
+mod text;
 
 pub use btree::BTreeIndex;
+pub use btree_composite::CompositeBTreeIndex;
+pub use btree_multi::MultiBTreeIndex;
+pub use btree_multi_unique::UniqueMultiBTreeIndex;
 pub use btree_unique::UniqueBTreeIndex;
+pub use handle::IndexHandle;
+pub use hash::HashIndex;
+pub use hash_unique::UniqueHashIndex;
+pub use partial::PartialIndex;
+pub use prefix::PrefixIndex;
+#[cfg(feature = "rtree")]
+pub use rtree::{BoundingBox, RTreeIndex, SpatialQuery};
+pub use text::{Lowercase, Stem, TextIndex, TextQuery, Tokenizer, Whitespace};
 
-pub trait Index<T: Identity> {
+/// Index over the elements of a table.
+///
+/// Indices of a [`Shared`](crate::table::Shared) table, and of the snapshots taken from it, have
+/// to be `Send + Sync` as well, see [`SharedIndex`]. Operations only some indices support, like
+/// ranked searches, are on separate traits such as [`SearchIndex`], and are reached through an
+/// [`IndexHandle`] that knows the type of the index.
+pub trait Index<T: Identity>: Any {
     /// Remove all elements from the index.
     fn clear(&mut self);
 
@@ -19,27 +46,191 @@ pub trait Index<T: Identity> {
     fn insert(&mut self, value: &T) -> Result<(), IndexError<T>>;
 
     /// Insert multiple elements into the index.
-    fn insert_bulk(
+    ///
+    /// Either all elements are inserted, or none are and the first error is returned.
+    fn insert_bulk<'a>(
         &mut self,
-        mut values: Box<dyn Iterator<Item = &T>>,
+        values: Box<dyn Iterator<Item = &'a T> + 'a>,
     ) -> Result<(), IndexError<T>> {
-        unimplemented!()
+        let mut inserted = Vec::new();
+        for value in values {
+            if let Err(error) = self.insert(value) {
+                for value in inserted {
+                    let _ = self.remove(value);
+                }
+                return Err(error);
+            }
+            inserted.push(value);
+        }
+        Ok(())
     }
 
     /// Remove an element from the index.
     fn remove(&mut self, value: &T) -> Result<(), IndexError<T>>;
 
-    /// Insert multiple elements into the index.
-    fn remove_bulk(
+    /// Remove multiple elements from the index.
+    fn remove_bulk<'a>(
         &mut self,
-        mut values: Box<dyn Iterator<Item = &T>>,
+        values: Box<dyn Iterator<Item = &'a T> + 'a>,
     ) -> Result<(), IndexError<T>> {
-        unimplemented!()
+        for value in values {
+            self.remove(value)?;
+        }
+        Ok(())
     }
 
     /// Lookup a key in this index.
     fn lookup(
-        &mut self,
+        &self,
         key: &dyn Any,
-    ) -> Result<Box<dyn Iterator<Item = T::PrimaryKey>>, IndexError<T>>;
+    ) -> Result<Box<dyn Iterator<Item = T::PrimaryKey> + '_>, IndexError<T>>;
+
+    /// Lookup all keys within the bounds, in key order.
+    fn range(
+        &self,
+        _start: Bound<&dyn Any>,
+        _end: Bound<&dyn Any>,
+    ) -> Result<Box<dyn DoubleEndedIterator<Item = T::PrimaryKey> + '_>, IndexError<T>> {
+        Err(IndexError::Unsupported)
+    }
+
+    /// Determine if the key of the element in this index equals `key`.
+    ///
+    /// Used to check conditions on elements found through another index. The default looks
+    /// the key up, indices that can compute the key of an element should do that instead.
+    fn matches(&self, value: &T, key: &dyn Any) -> Result<bool, IndexError<T>> {
+        let primary_key = value.primary_key();
+        Ok(self.lookup(key)?.any(|key| key == primary_key))
+    }
+
+    /// Determine if the key of the element in this index is within the bounds.
+    ///
+    /// Like [`Index::matches`], the default looks the range up.
+    fn matches_range(
+        &self,
+        value: &T,
+        start: Bound<&dyn Any>,
+        end: Bound<&dyn Any>,
+    ) -> Result<bool, IndexError<T>> {
+        let primary_key = value.primary_key();
+        Ok(self.range(start, end)?.any(|key| key == primary_key))
+    }
+
+    /// Statistics on the contents of this index, if it keeps track of them.
+    fn stats(&self) -> Option<IndexStats> {
+        None
+    }
+
+    /// Take a read-only copy of the index as it is now, for a [`Snapshot`](crate::Snapshot).
+    ///
+    /// Indices built on persistent data structures can do this without copying their data.
+    /// Indices that return nothing cannot be queried through snapshots. The copy should be of
+    /// the same type as the index, otherwise typed handles cannot find it in the snapshot.
+    fn snapshot(&self) -> Option<Box<dyn Index<T>>> {
+        None
+    }
+}
+
+/// Index that can be shared between threads, as the indices of a
+/// [`Shared`](crate::table::Shared) table are.
+///
+/// Implemented for every index that is `Send + Sync`, its snapshots are then `Send + Sync` too.
+pub trait SharedIndex<T: Identity>: Index<T> + Send + Sync {
+    /// Take a snapshot of the index, see [`Index::snapshot`].
+    fn snapshot_shared(&self) -> Option<Box<dyn SharedIndex<T>>>;
+}
+
+impl<T: Identity, I: Index<T> + Send + Sync> SharedIndex<T> for I {
+    fn snapshot_shared(&self) -> Option<Box<dyn SharedIndex<T>>> {
+        // snapshots are of the same type as the index, so they are `Send + Sync` as well.
+        let snapshot: Box<dyn Any> = self.snapshot()?;
+        Some(snapshot.downcast::<I>().ok()?)
+    }
+}
+
+/// Statistics on the contents of an index, used to plan queries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IndexStats {
+    /// Number of distinct keys.
+    pub keys: usize,
+    /// Number of elements in the index.
+    pub entries: usize,
+}
+
+impl IndexStats {
+    /// Average number of elements per key.
+    pub fn average(&self) -> f64 {
+        match self.keys {
+            0 => 0.0,
+            keys => self.entries as f64 / keys as f64,
+        }
+    }
+}
+
+/// An index that knows the type of the keys it is looked up by.
+pub trait TypedIndex<T: Identity>: Index<T> {
+    /// Type of the keys of this index.
+    type Key: 'static;
+}
+
+/// An index that can rank the elements matching a key by relevance.
+pub trait SearchIndex<T: Identity>: TypedIndex<T> {
+    /// Lookup a key in this index, along with the score of every matching element, best first.
+    fn search(&self, key: &Self::Key) -> Vec<(T::PrimaryKey, f64)>;
+}
+
+/// An index that knows how many elements a lookup would return without going through them.
+pub trait CountIndex<T: Identity>: TypedIndex<T> {
+    /// Number of elements a lookup of the key would return.
+    fn count(&self, key: &Self::Key) -> usize;
+}
+
+/// An index on a key made of a prefix and a suffix, which can be looked up by prefix alone.
+pub trait CompositeIndex<T: Identity>: TypedIndex<T> {
+    /// Type of the first part of the keys.
+    type Prefix: 'static;
+    /// Type of the second part of the keys.
+    type Suffix: 'static;
+
+    /// Lookup all keys with this prefix, in suffix order.
+    fn prefix(
+        &self,
+        prefix: &Self::Prefix,
+    ) -> Box<dyn DoubleEndedIterator<Item = T::PrimaryKey> + '_>;
+
+    /// Lookup all keys with this prefix whose suffix is within the bounds, in suffix order.
+    fn prefix_range(
+        &self,
+        prefix: &Self::Prefix,
+        start: Bound<&Self::Suffix>,
+        end: Bound<&Self::Suffix>,
+    ) -> Box<dyn DoubleEndedIterator<Item = T::PrimaryKey> + '_>;
+}
+
+/// Downcast both bounds of a dynamic range to the key type of an index.
+pub(crate) fn downcast_bounds<'a, T: Identity, K: 'static>(
+    start: Bound<&'a dyn Any>,
+    end: Bound<&'a dyn Any>,
+) -> Result<(Bound<&'a K>, Bound<&'a K>), IndexError<T>> {
+    let downcast = |bound: Bound<&'a dyn Any>| match bound {
+        Bound::Included(key) => key.downcast_ref().map(Bound::Included),
+        Bound::Excluded(key) => key.downcast_ref().map(Bound::Excluded),
+        Bound::Unbounded => Some(Bound::Unbounded),
+    };
+    match (downcast(start), downcast(end)) {
+        (Some(start), Some(end)) => Ok((start, end)),
+        _ => Err(IndexError::KeyType),
+    }
+}
+
+/// Determine if a range is valid, meaning `BTreeMap::range` would not panic on it.
+pub(crate) fn range_is_valid<K: Ord>(start: Bound<&K>, end: Bound<&K>) -> bool {
+    match (start, end) {
+        (Bound::Excluded(start), Bound::Excluded(end)) => start < end,
+        (
+            Bound::Included(start) | Bound::Excluded(start),
+            Bound::Included(end) | Bound::Excluded(end),
+        ) => start <= end,
+        _ => true,
+    }
 }
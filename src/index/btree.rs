@@ -1,57 +1,70 @@
-use crate::index::Index;
+use crate::index::{downcast_bounds, range_is_valid, Index, IndexStats, TypedIndex};
 use crate::Identity;
 use crate::IndexError;
+use im::ordmap::Entry;
+use im::{OrdMap, OrdSet};
 use std::any::Any;
-use std::collections::btree_map::Entry;
 use std::collections::*;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
 
+/// Index that maps every key to the set of elements with that key, in key order.
+///
+/// Backed by persistent maps, so taking a snapshot of it is cheap. Keeps track of the number of
+/// elements, for the statistics used in query planning.
 #[derive(Default)]
-pub struct BTreeIndex<T: Identity, K: Ord + 'static, F: Fn(&T) -> K> {
-    map: F,
-    data: BTreeMap<K, BTreeSet<T::PrimaryKey>>,
+pub struct BTreeIndex<T: Identity, K: Ord + Clone + 'static, F: Fn(&T) -> K> {
+    map: Arc<F>,
+    data: OrdMap<K, OrdSet<T::PrimaryKey>>,
+    entries: usize,
 }
 
-impl<T: Identity, K: Ord + 'static, F: Fn(&T) -> K> BTreeIndex<T, K, F> {
+impl<T: Identity, K: Ord + Clone + 'static, F: Fn(&T) -> K> BTreeIndex<T, K, F> {
     pub fn new(map: F) -> Self {
         BTreeIndex {
-            map,
+            map: Arc::new(map),
             data: Default::default(),
+            entries: 0,
         }
     }
 
     pub fn insert(&mut self, element: &T) -> Result<(), IndexError<T>> {
-        let key = (self.map)(&element);
+        let key = (self.map)(element);
         match self.data.entry(key) {
             Entry::Vacant(entry) => {
-                let mut set = BTreeSet::new();
+                let mut set = OrdSet::new();
                 set.insert(element.primary_key());
                 entry.insert(set);
+                self.entries += 1;
                 Ok(())
             }
             Entry::Occupied(mut value) => {
-                let mut set = value.get_mut();
-                set.insert(element.primary_key());
+                let set = value.get_mut();
+                if set.insert(element.primary_key()).is_none() {
+                    self.entries += 1;
+                }
                 Ok(())
             }
         }
     }
 
     pub fn remove(&mut self, element: &T) -> Result<(), IndexError<T>> {
-        let key = (self.map)(&element);
+        let key = (self.map)(element);
         match self.data.entry(key) {
             Entry::Occupied(mut value) => {
-                let mut set = value.get_mut();
-                set.remove(&element.primary_key());
+                let set = value.get_mut();
+                if set.remove(&element.primary_key()).is_some() {
+                    self.entries -= 1;
+                }
 
                 // remove the entry altogether if the set is empty
-                if set.len() == 0 {
-                    drop(set);
+                if set.is_empty() {
                     value.remove();
                 }
 
                 Ok(())
             }
-            Entry::Vacant(entry) => {
+            Entry::Vacant(_) => {
                 // FIXME error?
                 //unimplemented!()
                 Ok(())
@@ -59,21 +72,110 @@ impl<T: Identity, K: Ord + 'static, F: Fn(&T) -> K> BTreeIndex<T, K, F> {
         }
     }
 
+    /// Insert multiple elements, grouping them by key so every key is only looked up once.
+    pub fn insert_bulk<'a>(
+        &mut self,
+        elements: impl IntoIterator<Item = &'a T>,
+    ) -> Result<(), IndexError<T>>
+    where
+        T: 'a,
+    {
+        let mut batch: BTreeMap<K, Vec<T::PrimaryKey>> = BTreeMap::new();
+        for element in elements {
+            batch
+                .entry((self.map)(element))
+                .or_default()
+                .push(element.primary_key());
+        }
+
+        if self.data.is_empty() {
+            self.data = batch
+                .into_iter()
+                .map(|(key, keys)| (key, OrdSet::from(keys)))
+                .collect();
+            self.entries = self.data.values().map(OrdSet::len).sum();
+        } else {
+            for (key, keys) in batch {
+                let set = self.data.entry(key).or_default();
+                let before = set.len();
+                set.extend(keys);
+                self.entries += set.len() - before;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Remove multiple elements, grouping them by key so every key is only looked up once.
+    pub fn remove_bulk<'a>(
+        &mut self,
+        elements: impl IntoIterator<Item = &'a T>,
+    ) -> Result<(), IndexError<T>>
+    where
+        T: 'a,
+    {
+        let mut batch: BTreeMap<K, Vec<T::PrimaryKey>> = BTreeMap::new();
+        for element in elements {
+            batch
+                .entry((self.map)(element))
+                .or_default()
+                .push(element.primary_key());
+        }
+
+        for (key, keys) in batch {
+            if let Entry::Occupied(mut value) = self.data.entry(key) {
+                let set = value.get_mut();
+                for key in &keys {
+                    if set.remove(key).is_some() {
+                        self.entries -= 1;
+                    }
+                }
+                if set.is_empty() {
+                    value.remove();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn clear(&mut self) {
-        self.data.clear()
+        self.data.clear();
+        self.entries = 0;
     }
 
-    pub fn lookup(&self, key: &K) -> impl Iterator<Item = T::PrimaryKey> {
-        self.data
-            .get(key)
-            .cloned()
-            .map(|value| value.into_iter())
+    /// Number of distinct keys and elements in this index.
+    pub fn stats(&self) -> IndexStats {
+        IndexStats {
+            keys: self.data.len(),
+            entries: self.entries,
+        }
+    }
+
+    pub fn lookup(&self, key: &K) -> impl Iterator<Item = T::PrimaryKey> + '_ {
+        self.data.get(key).into_iter().flatten().cloned()
+    }
+
+    /// Lookup all keys within the range, in key order.
+    pub fn range<R: RangeBounds<K>>(
+        &self,
+        range: R,
+    ) -> impl DoubleEndedIterator<Item = T::PrimaryKey> + '_ {
+        let valid = range_is_valid(range.start_bound(), range.end_bound());
+        valid
+            .then(|| self.data.range(range))
             .into_iter()
             .flatten()
+            .flat_map(|(_, keys)| keys.iter().cloned())
     }
 }
 
-impl<T: Identity, K: Ord + 'static, F: Fn(&T) -> K> Index<T> for BTreeIndex<T, K, F> {
+impl<T, K, F> Index<T> for BTreeIndex<T, K, F>
+where
+    T: Identity + 'static,
+    K: Ord + Clone + 'static,
+    F: Fn(&T) -> K + 'static,
+{
     fn clear(&mut self) {
         self.clear()
     }
@@ -82,25 +184,77 @@ impl<T: Identity, K: Ord + 'static, F: Fn(&T) -> K> Index<T> for BTreeIndex<T, K
         self.insert(value)
     }
 
+    fn insert_bulk<'a>(
+        &mut self,
+        values: Box<dyn Iterator<Item = &'a T> + 'a>,
+    ) -> Result<(), IndexError<T>> {
+        self.insert_bulk(values)
+    }
+
     fn remove(&mut self, value: &T) -> Result<(), IndexError<T>> {
         self.remove(value)
     }
 
-    fn lookup(
+    fn remove_bulk<'a>(
         &mut self,
+        values: Box<dyn Iterator<Item = &'a T> + 'a>,
+    ) -> Result<(), IndexError<T>> {
+        self.remove_bulk(values)
+    }
+
+    fn lookup(
+        &self,
         key: &dyn Any,
-    ) -> Result<Box<dyn Iterator<Item = T::PrimaryKey>>, IndexError<T>> {
-        if let Some(key) = &key.downcast_ref::<K>() {
-            Ok(Box::new(
-                self.data
-                    .get(key)
-                    .cloned()
-                    .map(|value| value.into_iter())
-                    .into_iter()
-                    .flatten(),
-            ))
+    ) -> Result<Box<dyn Iterator<Item = T::PrimaryKey> + '_>, IndexError<T>> {
+        if let Some(key) = key.downcast_ref::<K>() {
+            Ok(Box::new(self.lookup(key)))
         } else {
             Err(IndexError::KeyType)
         }
     }
+
+    fn range(
+        &self,
+        start: Bound<&dyn Any>,
+        end: Bound<&dyn Any>,
+    ) -> Result<Box<dyn DoubleEndedIterator<Item = T::PrimaryKey> + '_>, IndexError<T>> {
+        let (start, end) = downcast_bounds::<T, K>(start, end)?;
+        Ok(Box::new(self.range((start, end))))
+    }
+
+    fn matches(&self, value: &T, key: &dyn Any) -> Result<bool, IndexError<T>> {
+        let key = key.downcast_ref::<K>().ok_or(IndexError::KeyType)?;
+        Ok(&(self.map)(value) == key)
+    }
+
+    fn matches_range(
+        &self,
+        value: &T,
+        start: Bound<&dyn Any>,
+        end: Bound<&dyn Any>,
+    ) -> Result<bool, IndexError<T>> {
+        let bounds = downcast_bounds::<T, K>(start, end)?;
+        Ok(bounds.contains(&(self.map)(value)))
+    }
+
+    fn stats(&self) -> Option<IndexStats> {
+        Some(self.stats())
+    }
+
+    fn snapshot(&self) -> Option<Box<dyn Index<T>>> {
+        Some(Box::new(BTreeIndex {
+            map: self.map.clone(),
+            data: self.data.clone(),
+            entries: self.entries,
+        }))
+    }
+}
+
+impl<T, K, F> TypedIndex<T> for BTreeIndex<T, K, F>
+where
+    T: Identity + 'static,
+    K: Ord + Clone + 'static,
+    F: Fn(&T) -> K + 'static,
+{
+    type Key = K;
 }
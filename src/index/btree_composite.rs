@@ -0,0 +1,277 @@
+use crate::index::{
+    downcast_bounds, range_is_valid, CompositeIndex, Index, IndexStats, TypedIndex,
+};
+use crate::Identity;
+use crate::IndexError;
+use im::ordmap::Entry;
+use im::{OrdMap, OrdSet};
+use std::any::Any;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+/// Index on a key made of two parts, a prefix and a suffix, in key order.
+///
+/// Besides lookups on the whole key, elements can be looked up by prefix alone, ordered by
+/// suffix, and by a range of suffixes within one prefix. Through the dynamic interface, a lookup
+/// with a key of type `(P, S)` matches the whole key, while one with a key of type `P` matches
+/// the prefix.
+#[derive(Default)]
+pub struct CompositeBTreeIndex<T, P, S, F>
+where
+    T: Identity,
+    P: Ord + Clone + 'static,
+    S: Ord + Clone + 'static,
+    F: Fn(&T) -> (P, S),
+{
+    map: Arc<F>,
+    data: OrdMap<P, OrdMap<S, OrdSet<T::PrimaryKey>>>,
+    keys: usize,
+    entries: usize,
+}
+
+impl<T, P, S, F> CompositeBTreeIndex<T, P, S, F>
+where
+    T: Identity,
+    P: Ord + Clone + 'static,
+    S: Ord + Clone + 'static,
+    F: Fn(&T) -> (P, S),
+{
+    pub fn new(map: F) -> Self {
+        CompositeBTreeIndex {
+            map: Arc::new(map),
+            data: Default::default(),
+            keys: 0,
+            entries: 0,
+        }
+    }
+
+    pub fn insert(&mut self, element: &T) -> Result<(), IndexError<T>> {
+        let (prefix, suffix) = (self.map)(element);
+        let suffixes = self.data.entry(prefix).or_default();
+        if !suffixes.contains_key(&suffix) {
+            self.keys += 1;
+        }
+        let keys = suffixes.entry(suffix).or_default();
+        if keys.insert(element.primary_key()).is_none() {
+            self.entries += 1;
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, element: &T) -> Result<(), IndexError<T>> {
+        let (prefix, suffix) = (self.map)(element);
+        if let Entry::Occupied(mut suffixes) = self.data.entry(prefix) {
+            if let Entry::Occupied(mut keys) = suffixes.get_mut().entry(suffix) {
+                if keys.get_mut().remove(&element.primary_key()).is_some() {
+                    self.entries -= 1;
+                }
+
+                // remove the entries altogether if they are empty
+                if keys.get().is_empty() {
+                    keys.remove();
+                    self.keys -= 1;
+                }
+            }
+            if suffixes.get().is_empty() {
+                suffixes.remove();
+            }
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.keys = 0;
+        self.entries = 0;
+    }
+
+    /// Number of distinct whole keys and elements in this index.
+    pub fn stats(&self) -> IndexStats {
+        IndexStats {
+            keys: self.keys,
+            entries: self.entries,
+        }
+    }
+
+    /// Lookup all keys matching the whole key.
+    pub fn lookup(&self, key: &(P, S)) -> impl Iterator<Item = T::PrimaryKey> + '_ {
+        self.data
+            .get(&key.0)
+            .and_then(|suffixes| suffixes.get(&key.1))
+            .into_iter()
+            .flatten()
+            .cloned()
+    }
+
+    /// Lookup all keys with this prefix, in suffix order.
+    pub fn prefix(&self, prefix: &P) -> impl DoubleEndedIterator<Item = T::PrimaryKey> + '_ {
+        self.data
+            .get(prefix)
+            .into_iter()
+            .flat_map(|suffixes| suffixes.values())
+            .flat_map(|keys| keys.iter().cloned())
+    }
+
+    /// Lookup all keys with this prefix whose suffix is within the range, in suffix order.
+    pub fn prefix_range<R: RangeBounds<S>>(
+        &self,
+        prefix: &P,
+        range: R,
+    ) -> impl DoubleEndedIterator<Item = T::PrimaryKey> + '_ {
+        let valid = range_is_valid(range.start_bound(), range.end_bound());
+        let suffixes = valid.then(|| self.data.get(prefix)).flatten();
+        suffixes
+            .map(|suffixes| suffixes.range(range))
+            .into_iter()
+            .flatten()
+            .flat_map(|(_, keys)| keys.iter().cloned())
+    }
+
+    /// Lookup all keys within the range of whole keys, in key order.
+    pub fn range<R: RangeBounds<(P, S)>>(
+        &self,
+        range: R,
+    ) -> impl DoubleEndedIterator<Item = T::PrimaryKey> + '_ {
+        let valid = range_is_valid(range.start_bound(), range.end_bound());
+        let start = range.start_bound().cloned();
+        let end = range.end_bound().cloned();
+        let prefixes = (prefix_bound(&start), prefix_bound(&end));
+        valid
+            .then(|| self.data.range(prefixes))
+            .into_iter()
+            .flatten()
+            .flat_map(move |(prefix, suffixes)| {
+                suffixes.range((suffix_bound(&start, prefix), suffix_bound(&end, prefix)))
+            })
+            .flat_map(|(_, keys)| keys.iter().cloned())
+    }
+}
+
+/// Bound on the prefixes implied by a bound on whole keys.
+fn prefix_bound<P: Clone, S>(bound: &Bound<(P, S)>) -> Bound<P> {
+    match bound {
+        Bound::Included((prefix, _)) | Bound::Excluded((prefix, _)) => {
+            Bound::Included(prefix.clone())
+        }
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// Bound on the suffixes of one prefix implied by a bound on whole keys.
+fn suffix_bound<P: Eq, S: Clone>(bound: &Bound<(P, S)>, prefix: &P) -> Bound<S> {
+    match bound {
+        Bound::Included((key, suffix)) if key == prefix => Bound::Included(suffix.clone()),
+        Bound::Excluded((key, suffix)) if key == prefix => Bound::Excluded(suffix.clone()),
+        _ => Bound::Unbounded,
+    }
+}
+
+impl<T, P, S, F> Index<T> for CompositeBTreeIndex<T, P, S, F>
+where
+    T: Identity + 'static,
+    P: Ord + Clone + 'static,
+    S: Ord + Clone + 'static,
+    F: Fn(&T) -> (P, S) + 'static,
+{
+    fn clear(&mut self) {
+        self.clear()
+    }
+
+    fn insert(&mut self, value: &T) -> Result<(), IndexError<T>> {
+        self.insert(value)
+    }
+
+    fn remove(&mut self, value: &T) -> Result<(), IndexError<T>> {
+        self.remove(value)
+    }
+
+    fn lookup(
+        &self,
+        key: &dyn Any,
+    ) -> Result<Box<dyn Iterator<Item = T::PrimaryKey> + '_>, IndexError<T>> {
+        if let Some(key) = key.downcast_ref::<(P, S)>() {
+            Ok(Box::new(self.lookup(key)))
+        } else if let Some(prefix) = key.downcast_ref::<P>() {
+            Ok(Box::new(self.prefix(prefix)))
+        } else {
+            Err(IndexError::KeyType)
+        }
+    }
+
+    fn range(
+        &self,
+        start: Bound<&dyn Any>,
+        end: Bound<&dyn Any>,
+    ) -> Result<Box<dyn DoubleEndedIterator<Item = T::PrimaryKey> + '_>, IndexError<T>> {
+        let (start, end) = downcast_bounds::<T, (P, S)>(start, end)?;
+        Ok(Box::new(self.range((start, end))))
+    }
+
+    fn matches(&self, value: &T, key: &dyn Any) -> Result<bool, IndexError<T>> {
+        let (prefix, suffix) = (self.map)(value);
+        if let Some(key) = key.downcast_ref::<(P, S)>() {
+            Ok(key.0 == prefix && key.1 == suffix)
+        } else if let Some(key) = key.downcast_ref::<P>() {
+            Ok(key == &prefix)
+        } else {
+            Err(IndexError::KeyType)
+        }
+    }
+
+    fn matches_range(
+        &self,
+        value: &T,
+        start: Bound<&dyn Any>,
+        end: Bound<&dyn Any>,
+    ) -> Result<bool, IndexError<T>> {
+        let bounds = downcast_bounds::<T, (P, S)>(start, end)?;
+        Ok(bounds.contains(&(self.map)(value)))
+    }
+
+    fn stats(&self) -> Option<IndexStats> {
+        Some(self.stats())
+    }
+
+    fn snapshot(&self) -> Option<Box<dyn Index<T>>> {
+        Some(Box::new(CompositeBTreeIndex {
+            map: self.map.clone(),
+            data: self.data.clone(),
+            keys: self.keys,
+            entries: self.entries,
+        }))
+    }
+}
+
+impl<T, P, S, F> TypedIndex<T> for CompositeBTreeIndex<T, P, S, F>
+where
+    T: Identity + 'static,
+    P: Ord + Clone + 'static,
+    S: Ord + Clone + 'static,
+    F: Fn(&T) -> (P, S) + 'static,
+{
+    type Key = (P, S);
+}
+
+impl<T, P, S, F> CompositeIndex<T> for CompositeBTreeIndex<T, P, S, F>
+where
+    T: Identity + 'static,
+    P: Ord + Clone + 'static,
+    S: Ord + Clone + 'static,
+    F: Fn(&T) -> (P, S) + 'static,
+{
+    type Prefix = P;
+    type Suffix = S;
+
+    fn prefix(&self, prefix: &P) -> Box<dyn DoubleEndedIterator<Item = T::PrimaryKey> + '_> {
+        Box::new(self.prefix(prefix))
+    }
+
+    fn prefix_range(
+        &self,
+        prefix: &P,
+        start: Bound<&S>,
+        end: Bound<&S>,
+    ) -> Box<dyn DoubleEndedIterator<Item = T::PrimaryKey> + '_> {
+        Box::new(self.prefix_range(prefix, (start, end)))
+    }
+}
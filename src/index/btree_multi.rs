@@ -0,0 +1,186 @@
+use crate::index::{downcast_bounds, range_is_valid, Index, IndexStats, TypedIndex};
+use crate::Identity;
+use crate::IndexError;
+use im::ordmap::Entry;
+use im::{OrdMap, OrdSet};
+use std::any::Any;
+use std::collections::BTreeSet;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+/// Index that maps every key to the set of elements with that key, where every element can have
+/// any number of keys, in key order.
+///
+/// Used for fields holding several values, like tags or aliases. Keys listed more than once for
+/// the same element count once, and elements without keys are not in the index. Range lookups
+/// return an element once for every one of its keys within the range.
+pub struct MultiBTreeIndex<T, K, F, I>
+where
+    T: Identity,
+    K: Ord + Clone + 'static,
+    F: Fn(&T) -> I,
+    I: IntoIterator<Item = K>,
+{
+    map: Arc<F>,
+    data: OrdMap<K, OrdSet<T::PrimaryKey>>,
+    entries: usize,
+}
+
+impl<T, K, F, I> MultiBTreeIndex<T, K, F, I>
+where
+    T: Identity,
+    K: Ord + Clone + 'static,
+    F: Fn(&T) -> I,
+    I: IntoIterator<Item = K>,
+{
+    pub fn new(map: F) -> Self {
+        MultiBTreeIndex {
+            map: Arc::new(map),
+            data: Default::default(),
+            entries: 0,
+        }
+    }
+
+    /// Distinct keys of an element.
+    fn keys(&self, element: &T) -> BTreeSet<K> {
+        (self.map)(element).into_iter().collect()
+    }
+
+    pub fn insert(&mut self, element: &T) -> Result<(), IndexError<T>> {
+        let primary_key = element.primary_key();
+        for key in self.keys(element) {
+            let set = self.data.entry(key).or_default();
+            if set.insert(primary_key.clone()).is_none() {
+                self.entries += 1;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, element: &T) -> Result<(), IndexError<T>> {
+        let primary_key = element.primary_key();
+        for key in self.keys(element) {
+            if let Entry::Occupied(mut value) = self.data.entry(key) {
+                let set = value.get_mut();
+                if set.remove(&primary_key).is_some() {
+                    self.entries -= 1;
+                }
+
+                // remove the entry altogether if the set is empty
+                if set.is_empty() {
+                    value.remove();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.entries = 0;
+    }
+
+    /// Number of distinct keys, and of pairs of a key and an element with that key.
+    pub fn stats(&self) -> IndexStats {
+        IndexStats {
+            keys: self.data.len(),
+            entries: self.entries,
+        }
+    }
+
+    pub fn lookup(&self, key: &K) -> impl Iterator<Item = T::PrimaryKey> + '_ {
+        self.data.get(key).into_iter().flatten().cloned()
+    }
+
+    /// Lookup all keys within the range, in key order.
+    pub fn range<R: RangeBounds<K>>(
+        &self,
+        range: R,
+    ) -> impl DoubleEndedIterator<Item = T::PrimaryKey> + '_ {
+        let valid = range_is_valid(range.start_bound(), range.end_bound());
+        valid
+            .then(|| self.data.range(range))
+            .into_iter()
+            .flatten()
+            .flat_map(|(_, keys)| keys.iter().cloned())
+    }
+}
+
+impl<T, K, F, I> Index<T> for MultiBTreeIndex<T, K, F, I>
+where
+    T: Identity + 'static,
+    K: Ord + Clone + 'static,
+    F: Fn(&T) -> I + 'static,
+    I: IntoIterator<Item = K> + 'static,
+{
+    fn clear(&mut self) {
+        self.clear()
+    }
+
+    fn insert(&mut self, value: &T) -> Result<(), IndexError<T>> {
+        self.insert(value)
+    }
+
+    fn remove(&mut self, value: &T) -> Result<(), IndexError<T>> {
+        self.remove(value)
+    }
+
+    fn lookup(
+        &self,
+        key: &dyn Any,
+    ) -> Result<Box<dyn Iterator<Item = T::PrimaryKey> + '_>, IndexError<T>> {
+        if let Some(key) = key.downcast_ref::<K>() {
+            Ok(Box::new(self.lookup(key)))
+        } else {
+            Err(IndexError::KeyType)
+        }
+    }
+
+    fn range(
+        &self,
+        start: Bound<&dyn Any>,
+        end: Bound<&dyn Any>,
+    ) -> Result<Box<dyn DoubleEndedIterator<Item = T::PrimaryKey> + '_>, IndexError<T>> {
+        let (start, end) = downcast_bounds::<T, K>(start, end)?;
+        Ok(Box::new(self.range((start, end))))
+    }
+
+    fn matches(&self, value: &T, key: &dyn Any) -> Result<bool, IndexError<T>> {
+        let key = key.downcast_ref::<K>().ok_or(IndexError::KeyType)?;
+        Ok((self.map)(value).into_iter().any(|value| &value == key))
+    }
+
+    fn matches_range(
+        &self,
+        value: &T,
+        start: Bound<&dyn Any>,
+        end: Bound<&dyn Any>,
+    ) -> Result<bool, IndexError<T>> {
+        let bounds = downcast_bounds::<T, K>(start, end)?;
+        Ok((self.map)(value)
+            .into_iter()
+            .any(|key| bounds.contains(&key)))
+    }
+
+    fn stats(&self) -> Option<IndexStats> {
+        Some(self.stats())
+    }
+
+    fn snapshot(&self) -> Option<Box<dyn Index<T>>> {
+        Some(Box::new(MultiBTreeIndex {
+            map: self.map.clone(),
+            data: self.data.clone(),
+            entries: self.entries,
+        }))
+    }
+}
+
+impl<T, K, F, I> TypedIndex<T> for MultiBTreeIndex<T, K, F, I>
+where
+    T: Identity + 'static,
+    K: Ord + Clone + 'static,
+    F: Fn(&T) -> I + 'static,
+    I: IntoIterator<Item = K> + 'static,
+{
+    type Key = K;
+}
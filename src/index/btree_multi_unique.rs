@@ -0,0 +1,179 @@
+use crate::index::{downcast_bounds, range_is_valid, Index, IndexStats, TypedIndex};
+use crate::Identity;
+use crate::IndexError;
+use im::ordmap::Entry;
+use im::OrdMap;
+use std::any::Any;
+use std::collections::BTreeSet;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+/// Index that maps every key to the single element with that key, where every element can have
+/// any number of keys, in key order.
+///
+/// Used for fields holding several values that identify an element, like email aliases. Keys
+/// listed more than once for the same element count once, and an element is rejected if any of
+/// its keys belongs to another element. Range lookups return an element once for every one of
+/// its keys within the range.
+pub struct UniqueMultiBTreeIndex<T, K, F, I>
+where
+    T: Identity,
+    K: Ord + Clone + 'static,
+    F: Fn(&T) -> I,
+    I: IntoIterator<Item = K>,
+{
+    map: Arc<F>,
+    data: OrdMap<K, T::PrimaryKey>,
+}
+
+impl<T, K, F, I> UniqueMultiBTreeIndex<T, K, F, I>
+where
+    T: Identity,
+    K: Ord + Clone + 'static,
+    F: Fn(&T) -> I,
+    I: IntoIterator<Item = K>,
+{
+    pub fn new(map: F) -> Self {
+        UniqueMultiBTreeIndex {
+            map: Arc::new(map),
+            data: Default::default(),
+        }
+    }
+
+    /// Distinct keys of an element.
+    fn keys(&self, element: &T) -> BTreeSet<K> {
+        (self.map)(element).into_iter().collect()
+    }
+
+    /// Insert an element, checking all of its keys before modifying the index.
+    pub fn insert(&mut self, element: &T) -> Result<(), IndexError<T>> {
+        let primary_key = element.primary_key();
+        let keys = self.keys(element);
+        for key in &keys {
+            match self.data.get(key) {
+                Some(existing) if existing != &primary_key => {
+                    return Err(IndexError::Duplicate(existing.clone()));
+                }
+                _ => {}
+            }
+        }
+        for key in keys {
+            self.data.insert(key, primary_key.clone());
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, element: &T) -> Result<(), IndexError<T>> {
+        let primary_key = element.primary_key();
+        for key in self.keys(element) {
+            if let Entry::Occupied(value) = self.data.entry(key) {
+                if value.get() == &primary_key {
+                    value.remove();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear()
+    }
+
+    pub fn lookup(&self, key: &K) -> impl Iterator<Item = T::PrimaryKey> + '_ {
+        self.data.get(key).cloned().into_iter()
+    }
+
+    /// Lookup all keys within the range, in key order.
+    pub fn range<R: RangeBounds<K>>(
+        &self,
+        range: R,
+    ) -> impl DoubleEndedIterator<Item = T::PrimaryKey> + '_ {
+        let valid = range_is_valid(range.start_bound(), range.end_bound());
+        valid
+            .then(|| self.data.range(range))
+            .into_iter()
+            .flatten()
+            .map(|(_, key)| key.clone())
+    }
+}
+
+impl<T, K, F, I> Index<T> for UniqueMultiBTreeIndex<T, K, F, I>
+where
+    T: Identity + 'static,
+    K: Ord + Clone + 'static,
+    F: Fn(&T) -> I + 'static,
+    I: IntoIterator<Item = K> + 'static,
+{
+    fn clear(&mut self) {
+        self.clear()
+    }
+
+    fn insert(&mut self, value: &T) -> Result<(), IndexError<T>> {
+        self.insert(value)
+    }
+
+    fn remove(&mut self, value: &T) -> Result<(), IndexError<T>> {
+        self.remove(value)
+    }
+
+    fn lookup(
+        &self,
+        key: &dyn Any,
+    ) -> Result<Box<dyn Iterator<Item = T::PrimaryKey> + '_>, IndexError<T>> {
+        if let Some(key) = key.downcast_ref::<K>() {
+            Ok(Box::new(self.lookup(key)))
+        } else {
+            Err(IndexError::KeyType)
+        }
+    }
+
+    fn range(
+        &self,
+        start: Bound<&dyn Any>,
+        end: Bound<&dyn Any>,
+    ) -> Result<Box<dyn DoubleEndedIterator<Item = T::PrimaryKey> + '_>, IndexError<T>> {
+        let (start, end) = downcast_bounds::<T, K>(start, end)?;
+        Ok(Box::new(self.range((start, end))))
+    }
+
+    fn matches(&self, value: &T, key: &dyn Any) -> Result<bool, IndexError<T>> {
+        let key = key.downcast_ref::<K>().ok_or(IndexError::KeyType)?;
+        Ok((self.map)(value).into_iter().any(|value| &value == key))
+    }
+
+    fn matches_range(
+        &self,
+        value: &T,
+        start: Bound<&dyn Any>,
+        end: Bound<&dyn Any>,
+    ) -> Result<bool, IndexError<T>> {
+        let bounds = downcast_bounds::<T, K>(start, end)?;
+        Ok((self.map)(value)
+            .into_iter()
+            .any(|key| bounds.contains(&key)))
+    }
+
+    fn stats(&self) -> Option<IndexStats> {
+        Some(IndexStats {
+            keys: self.data.len(),
+            entries: self.data.len(),
+        })
+    }
+
+    fn snapshot(&self) -> Option<Box<dyn Index<T>>> {
+        Some(Box::new(UniqueMultiBTreeIndex {
+            map: self.map.clone(),
+            data: self.data.clone(),
+        }))
+    }
+}
+
+impl<T, K, F, I> TypedIndex<T> for UniqueMultiBTreeIndex<T, K, F, I>
+where
+    T: Identity + 'static,
+    K: Ord + Clone + 'static,
+    F: Fn(&T) -> I + 'static,
+    I: IntoIterator<Item = K> + 'static,
+{
+    type Key = K;
+}
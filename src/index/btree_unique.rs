@@ -1,26 +1,31 @@
-use crate::index::Index;
+use crate::index::{downcast_bounds, range_is_valid, Index, IndexStats, TypedIndex};
 use crate::Identity;
 use crate::IndexError;
+use im::ordmap::Entry;
+use im::OrdMap;
 use std::any::Any;
-use std::collections::btree_map::Entry;
-use std::collections::*;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
 
+/// Index that maps every key to the single element with that key, in key order.
+///
+/// Backed by a persistent map, so taking a snapshot of it is cheap.
 #[derive(Default)]
-pub struct UniqueBTreeIndex<T: Identity, K: Ord + 'static, F: Fn(&T) -> K> {
-    map: F,
-    data: BTreeMap<K, T::PrimaryKey>,
+pub struct UniqueBTreeIndex<T: Identity, K: Ord + Clone + 'static, F: Fn(&T) -> K> {
+    map: Arc<F>,
+    data: OrdMap<K, T::PrimaryKey>,
 }
 
-impl<T: Identity, K: Ord + 'static, F: Fn(&T) -> K> UniqueBTreeIndex<T, K, F> {
+impl<T: Identity, K: Ord + Clone + 'static, F: Fn(&T) -> K> UniqueBTreeIndex<T, K, F> {
     pub fn new(map: F) -> Self {
         UniqueBTreeIndex {
-            map,
+            map: Arc::new(map),
             data: Default::default(),
         }
     }
 
     pub fn insert(&mut self, element: &T) -> Result<(), IndexError<T>> {
-        let key = (self.map)(&element);
+        let key = (self.map)(element);
         match self.data.entry(key) {
             Entry::Vacant(entry) => {
                 entry.insert(element.primary_key());
@@ -31,33 +36,88 @@ impl<T: Identity, K: Ord + 'static, F: Fn(&T) -> K> UniqueBTreeIndex<T, K, F> {
     }
 
     pub fn remove(&mut self, element: &T) -> Result<(), IndexError<T>> {
-        let key = (self.map)(&element);
+        let key = (self.map)(element);
         match self.data.entry(key) {
             Entry::Occupied(value) if value.get() == &element.primary_key() => {
                 value.remove();
                 Ok(())
             }
-            Entry::Vacant(entry) => {
+            Entry::Vacant(_) => {
                 // FIXME error?
-                unimplemented!()
+                Ok(())
             }
-            Entry::Occupied(value) => {
+            Entry::Occupied(_) => {
                 // FIXME error?
                 Ok(())
             }
         }
     }
 
+    /// Insert multiple elements.
+    ///
+    /// All keys are checked for duplicates, against the index and within the batch, before the
+    /// index is modified, so either all elements are inserted or none are.
+    pub fn insert_bulk<'a>(
+        &mut self,
+        elements: impl IntoIterator<Item = &'a T>,
+    ) -> Result<(), IndexError<T>>
+    where
+        T: 'a,
+    {
+        let mut batch: Vec<(K, T::PrimaryKey)> = elements
+            .into_iter()
+            .map(|element| ((self.map)(element), element.primary_key()))
+            .collect();
+        batch.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        for pair in batch.windows(2) {
+            if pair[0].0 == pair[1].0 {
+                return Err(IndexError::Duplicate(pair[0].1.clone()));
+            }
+        }
+        for (key, _) in &batch {
+            if let Some(existing) = self.data.get(key) {
+                return Err(IndexError::Duplicate(existing.clone()));
+            }
+        }
+
+        if self.data.is_empty() {
+            self.data = batch.into_iter().collect();
+        } else {
+            self.data.extend(batch);
+        }
+
+        Ok(())
+    }
+
     pub fn clear(&mut self) {
         self.data.clear()
     }
 
-    pub fn lookup(&self, key: &K) -> impl Iterator<Item = T::PrimaryKey> {
+    pub fn lookup(&self, key: &K) -> impl Iterator<Item = T::PrimaryKey> + '_ {
         self.data.get(key).cloned().into_iter()
     }
+
+    /// Lookup all keys within the range, in key order.
+    pub fn range<R: RangeBounds<K>>(
+        &self,
+        range: R,
+    ) -> impl DoubleEndedIterator<Item = T::PrimaryKey> + '_ {
+        let valid = range_is_valid(range.start_bound(), range.end_bound());
+        valid
+            .then(|| self.data.range(range))
+            .into_iter()
+            .flatten()
+            .map(|(_, key)| key.clone())
+    }
 }
 
-impl<T: Identity, K: Ord + 'static, F: Fn(&T) -> K> Index<T> for UniqueBTreeIndex<T, K, F> {
+impl<T, K, F> Index<T> for UniqueBTreeIndex<T, K, F>
+where
+    T: Identity + 'static,
+    K: Ord + Clone + 'static,
+    F: Fn(&T) -> K + 'static,
+{
     fn clear(&mut self) {
         self.clear()
     }
@@ -66,18 +126,72 @@ impl<T: Identity, K: Ord + 'static, F: Fn(&T) -> K> Index<T> for UniqueBTreeInde
         self.insert(value)
     }
 
+    fn insert_bulk<'a>(
+        &mut self,
+        values: Box<dyn Iterator<Item = &'a T> + 'a>,
+    ) -> Result<(), IndexError<T>> {
+        self.insert_bulk(values)
+    }
+
     fn remove(&mut self, value: &T) -> Result<(), IndexError<T>> {
         self.remove(value)
     }
 
     fn lookup(
-        &mut self,
+        &self,
         key: &dyn Any,
-    ) -> Result<Box<dyn Iterator<Item = T::PrimaryKey>>, IndexError<T>> {
+    ) -> Result<Box<dyn Iterator<Item = T::PrimaryKey> + '_>, IndexError<T>> {
         if let Some(key) = key.downcast_ref::<K>() {
-            Ok(Box::new(self.data.get(key).cloned().into_iter()))
+            Ok(Box::new(self.lookup(key)))
         } else {
             Err(IndexError::KeyType)
         }
     }
+
+    fn range(
+        &self,
+        start: Bound<&dyn Any>,
+        end: Bound<&dyn Any>,
+    ) -> Result<Box<dyn DoubleEndedIterator<Item = T::PrimaryKey> + '_>, IndexError<T>> {
+        let (start, end) = downcast_bounds::<T, K>(start, end)?;
+        Ok(Box::new(self.range((start, end))))
+    }
+
+    fn matches(&self, value: &T, key: &dyn Any) -> Result<bool, IndexError<T>> {
+        let key = key.downcast_ref::<K>().ok_or(IndexError::KeyType)?;
+        Ok(&(self.map)(value) == key)
+    }
+
+    fn matches_range(
+        &self,
+        value: &T,
+        start: Bound<&dyn Any>,
+        end: Bound<&dyn Any>,
+    ) -> Result<bool, IndexError<T>> {
+        let bounds = downcast_bounds::<T, K>(start, end)?;
+        Ok(bounds.contains(&(self.map)(value)))
+    }
+
+    fn stats(&self) -> Option<IndexStats> {
+        Some(IndexStats {
+            keys: self.data.len(),
+            entries: self.data.len(),
+        })
+    }
+
+    fn snapshot(&self) -> Option<Box<dyn Index<T>>> {
+        Some(Box::new(UniqueBTreeIndex {
+            map: self.map.clone(),
+            data: self.data.clone(),
+        }))
+    }
+}
+
+impl<T, K, F> TypedIndex<T> for UniqueBTreeIndex<T, K, F>
+where
+    T: Identity + 'static,
+    K: Ord + Clone + 'static,
+    F: Fn(&T) -> K + 'static,
+{
+    type Key = K;
 }
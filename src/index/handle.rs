@@ -0,0 +1,159 @@
+use crate::index::{CompositeIndex, CountIndex, Index, SearchIndex, TypedIndex};
+use crate::{Identity, TableError, View};
+use std::any::Any;
+use std::marker::PhantomData;
+use std::ops::RangeBounds;
+
+/// Handle to an index registered in a [`Table`](crate::Table), which remembers the key type of the index.
+///
+/// Returned by [`Table::index_add`](crate::Table::index_add). Lookups through a handle are
+/// checked at compile time, so they cannot fail because of a wrong key type. Handles work on the
+/// table as well as on its snapshots.
+///
+/// The handle also remembers the type `I` of the index, which gives access to operations only
+/// some indices support, like [`search`](IndexHandle::search) on full-text indices. Handles of
+/// different index types with the same key type can be converted into a handle of the default
+/// type with [`From`], which only supports the operations of every index.
+pub struct IndexHandle<T: Identity, K, I: ?Sized = dyn Index<T>> {
+    name: String,
+    marker: PhantomData<fn() -> (T, K)>,
+    index: PhantomData<fn() -> *const I>,
+}
+
+impl<T: Identity, K, I: ?Sized> Clone for IndexHandle<T, K, I> {
+    fn clone(&self) -> Self {
+        IndexHandle {
+            name: self.name.clone(),
+            marker: PhantomData,
+            index: PhantomData,
+        }
+    }
+}
+
+impl<T: Identity, K, I: ?Sized> std::fmt::Debug for IndexHandle<T, K, I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("IndexHandle").field(&self.name).finish()
+    }
+}
+
+impl<T: Identity, K, I: TypedIndex<T>> From<IndexHandle<T, K, I>> for IndexHandle<T, K> {
+    fn from(handle: IndexHandle<T, K, I>) -> Self {
+        IndexHandle {
+            name: handle.name,
+            marker: PhantomData,
+            index: PhantomData,
+        }
+    }
+}
+
+impl<T: Identity, K: 'static, I: ?Sized> IndexHandle<T, K, I> {
+    pub(crate) fn new(name: &str) -> Self {
+        IndexHandle {
+            name: name.to_string(),
+            marker: PhantomData,
+            index: PhantomData,
+        }
+    }
+
+    /// Name of the index this handle refers to.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Lookup a key in this index, resolving the matching primary keys into rows.
+    ///
+    /// Fails with [`TableError::UnknownIndex`] if the index has since been removed from the
+    /// table, or replaced by one with a different key type.
+    pub fn lookup<'a>(
+        &self,
+        table: &'a impl View<T>,
+        key: &K,
+    ) -> Result<Box<dyn Iterator<Item = &'a T> + 'a>, TableError<T>> {
+        table
+            .index_lookup(&self.name, key)
+            .map_err(|error| self.error(error))
+    }
+
+    /// Lookup all rows whose key is within the range, in key order.
+    ///
+    /// The returned iterator can be reversed to visit the rows in descending key order.
+    pub fn range<'a, R: RangeBounds<K>>(
+        &self,
+        table: &'a impl View<T>,
+        range: R,
+    ) -> Result<Box<dyn DoubleEndedIterator<Item = &'a T> + 'a>, TableError<T>> {
+        let start = range.start_bound().map(|key| key as &dyn Any);
+        let end = range.end_bound().map(|key| key as &dyn Any);
+        table
+            .index_range(&self.name, start, end)
+            .map_err(|error| self.error(error))
+    }
+
+    /// Map errors of the untyped interface, a key type mismatch means this index is gone.
+    fn error(&self, error: TableError<T>) -> TableError<T> {
+        match error {
+            TableError::KeyType(name) => TableError::UnknownIndex(name),
+            error => error,
+        }
+    }
+}
+
+impl<T: Identity, I: TypedIndex<T>> IndexHandle<T, I::Key, I> {
+    /// Get the index this handle refers to from the table.
+    ///
+    /// Fails with [`TableError::UnknownIndex`] if the index has since been removed from the
+    /// table, or replaced by one of a different type.
+    pub fn index<'a>(&self, table: &'a impl View<T>) -> Result<&'a I, TableError<T>> {
+        let index: &dyn Any = table.index(&self.name)?;
+        index
+            .downcast_ref()
+            .ok_or_else(|| TableError::UnknownIndex(self.name.clone()))
+    }
+}
+
+impl<T: Identity, I: SearchIndex<T>> IndexHandle<T, I::Key, I> {
+    /// Search the index, returning the matching rows along with their score, best first.
+    pub fn search<'a>(
+        &self,
+        table: &'a impl View<T>,
+        key: &I::Key,
+    ) -> Result<Vec<(&'a T, f64)>, TableError<T>> {
+        let results = self.index(table)?.search(key);
+        Ok(results
+            .into_iter()
+            .filter_map(|(key, score)| Some((table.lookup(&key)?, score)))
+            .collect())
+    }
+}
+
+impl<T: Identity, I: CompositeIndex<T>> IndexHandle<T, I::Key, I> {
+    /// Lookup all rows with this prefix, ordered by the rest of the key.
+    pub fn prefix<'a>(
+        &self,
+        table: &'a impl View<T>,
+        prefix: &I::Prefix,
+    ) -> Result<Box<dyn DoubleEndedIterator<Item = &'a T> + 'a>, TableError<T>> {
+        let keys = self.index(table)?.prefix(prefix);
+        Ok(Box::new(keys.filter_map(|key| table.lookup(&key))))
+    }
+
+    /// Lookup all rows with this prefix whose rest of the key is within the range, in key order.
+    pub fn prefix_range<'a, R: RangeBounds<I::Suffix>>(
+        &self,
+        table: &'a impl View<T>,
+        prefix: &I::Prefix,
+        range: R,
+    ) -> Result<Box<dyn DoubleEndedIterator<Item = &'a T> + 'a>, TableError<T>> {
+        let keys = self
+            .index(table)?
+            .prefix_range(prefix, range.start_bound(), range.end_bound());
+        Ok(Box::new(keys.filter_map(|key| table.lookup(&key))))
+    }
+}
+
+impl<T: Identity, I: CountIndex<T>> IndexHandle<T, I::Key, I> {
+    /// Number of rows a lookup of this key would return, without going through them.
+    pub fn count(&self, table: &impl View<T>, key: &I::Key) -> Result<usize, TableError<T>> {
+        Ok(self.index(table)?.count(key))
+    }
+}
@@ -1,5 +1,148 @@
-#[derive(Default)]
-pub struct HashIndex {}
+use crate::index::{Index, IndexStats, TypedIndex};
+use crate::Identity;
+use crate::IndexError;
+use im::hashmap::Entry;
+use im::{HashMap, OrdSet};
+use std::any::Any;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::Arc;
 
 #[derive(Default)]
-pub struct UniqueHashIndex {}
+pub struct HashIndex<T: Identity, K, F, S = RandomState>
+where
+    K: Hash + Eq + Clone + 'static,
+    F: Fn(&T) -> K,
+    S: BuildHasher,
+{
+    map: Arc<F>,
+    data: HashMap<K, OrdSet<T::PrimaryKey>, S>,
+    entries: usize,
+}
+
+impl<T: Identity, K: Hash + Eq + Clone + 'static, F: Fn(&T) -> K> HashIndex<T, K, F> {
+    pub fn new(map: F) -> Self {
+        HashIndex::with_hasher(map, Default::default())
+    }
+}
+
+impl<T, K, F, S> HashIndex<T, K, F, S>
+where
+    T: Identity,
+    K: Hash + Eq + Clone + 'static,
+    F: Fn(&T) -> K,
+    S: BuildHasher,
+{
+    pub fn with_hasher(map: F, hasher: S) -> Self {
+        HashIndex {
+            map: Arc::new(map),
+            data: HashMap::with_hasher(hasher),
+            entries: 0,
+        }
+    }
+
+    pub fn insert(&mut self, element: &T) -> Result<(), IndexError<T>> {
+        let key = (self.map)(element);
+        if self
+            .data
+            .entry(key)
+            .or_default()
+            .insert(element.primary_key())
+            .is_none()
+        {
+            self.entries += 1;
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, element: &T) -> Result<(), IndexError<T>> {
+        let key = (self.map)(element);
+        if let Entry::Occupied(mut value) = self.data.entry(key) {
+            let set = value.get_mut();
+            if set.remove(&element.primary_key()).is_some() {
+                self.entries -= 1;
+            }
+
+            // remove the entry altogether if the set is empty
+            if set.is_empty() {
+                value.remove();
+            }
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.entries = 0;
+    }
+
+    /// Number of distinct keys and elements in this index.
+    pub fn stats(&self) -> IndexStats {
+        IndexStats {
+            keys: self.data.len(),
+            entries: self.entries,
+        }
+    }
+
+    pub fn lookup(&self, key: &K) -> impl Iterator<Item = T::PrimaryKey> + '_ {
+        self.data.get(key).into_iter().flatten().cloned()
+    }
+}
+
+impl<T, K, F, S> Index<T> for HashIndex<T, K, F, S>
+where
+    T: Identity + 'static,
+    K: Hash + Eq + Clone + 'static,
+    F: Fn(&T) -> K + 'static,
+    S: BuildHasher + 'static,
+{
+    fn clear(&mut self) {
+        self.clear()
+    }
+
+    fn insert(&mut self, value: &T) -> Result<(), IndexError<T>> {
+        self.insert(value)
+    }
+
+    fn remove(&mut self, value: &T) -> Result<(), IndexError<T>> {
+        self.remove(value)
+    }
+
+    fn lookup(
+        &self,
+        key: &dyn Any,
+    ) -> Result<Box<dyn Iterator<Item = T::PrimaryKey> + '_>, IndexError<T>> {
+        if let Some(key) = key.downcast_ref::<K>() {
+            Ok(Box::new(self.lookup(key)))
+        } else {
+            Err(IndexError::KeyType)
+        }
+    }
+
+    fn matches(&self, value: &T, key: &dyn Any) -> Result<bool, IndexError<T>> {
+        let key = key.downcast_ref::<K>().ok_or(IndexError::KeyType)?;
+        Ok(&(self.map)(value) == key)
+    }
+
+    fn stats(&self) -> Option<IndexStats> {
+        Some(self.stats())
+    }
+
+    fn snapshot(&self) -> Option<Box<dyn Index<T>>> {
+        Some(Box::new(HashIndex {
+            map: self.map.clone(),
+            data: self.data.clone(),
+            entries: self.entries,
+        }))
+    }
+}
+
+impl<T, K, F, S> TypedIndex<T> for HashIndex<T, K, F, S>
+where
+    T: Identity + 'static,
+    K: Hash + Eq + Clone + 'static,
+    F: Fn(&T) -> K + 'static,
+    S: BuildHasher + 'static,
+{
+    type Key = K;
+}
@@ -0,0 +1,132 @@
+use crate::index::{Index, IndexStats, TypedIndex};
+use crate::Identity;
+use crate::IndexError;
+use im::hashmap::Entry;
+use im::HashMap;
+use std::any::Any;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::sync::Arc;
+
+#[derive(Default)]
+pub struct UniqueHashIndex<T: Identity, K, F, S = RandomState>
+where
+    K: Hash + Eq + Clone + 'static,
+    F: Fn(&T) -> K,
+    S: BuildHasher,
+{
+    map: Arc<F>,
+    data: HashMap<K, T::PrimaryKey, S>,
+}
+
+impl<T: Identity, K: Hash + Eq + Clone + 'static, F: Fn(&T) -> K> UniqueHashIndex<T, K, F> {
+    pub fn new(map: F) -> Self {
+        UniqueHashIndex::with_hasher(map, Default::default())
+    }
+}
+
+impl<T, K, F, S> UniqueHashIndex<T, K, F, S>
+where
+    T: Identity,
+    K: Hash + Eq + Clone + 'static,
+    F: Fn(&T) -> K,
+    S: BuildHasher,
+{
+    pub fn with_hasher(map: F, hasher: S) -> Self {
+        UniqueHashIndex {
+            map: Arc::new(map),
+            data: HashMap::with_hasher(hasher),
+        }
+    }
+
+    pub fn insert(&mut self, element: &T) -> Result<(), IndexError<T>> {
+        let key = (self.map)(element);
+        match self.data.entry(key) {
+            Entry::Vacant(entry) => {
+                entry.insert(element.primary_key());
+                Ok(())
+            }
+            Entry::Occupied(value) => Err(IndexError::Duplicate(value.get().clone())),
+        }
+    }
+
+    pub fn remove(&mut self, element: &T) -> Result<(), IndexError<T>> {
+        let key = (self.map)(element);
+        match self.data.entry(key) {
+            Entry::Occupied(value) if value.get() == &element.primary_key() => {
+                value.remove();
+                Ok(())
+            }
+            // entry missing or owned by another element, nothing to remove.
+            _ => Ok(()),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear()
+    }
+
+    pub fn lookup(&self, key: &K) -> impl Iterator<Item = T::PrimaryKey> + '_ {
+        self.data.get(key).cloned().into_iter()
+    }
+}
+
+impl<T, K, F, S> Index<T> for UniqueHashIndex<T, K, F, S>
+where
+    T: Identity + 'static,
+    K: Hash + Eq + Clone + 'static,
+    F: Fn(&T) -> K + 'static,
+    S: BuildHasher + 'static,
+{
+    fn clear(&mut self) {
+        self.clear()
+    }
+
+    fn insert(&mut self, value: &T) -> Result<(), IndexError<T>> {
+        self.insert(value)
+    }
+
+    fn remove(&mut self, value: &T) -> Result<(), IndexError<T>> {
+        self.remove(value)
+    }
+
+    fn lookup(
+        &self,
+        key: &dyn Any,
+    ) -> Result<Box<dyn Iterator<Item = T::PrimaryKey> + '_>, IndexError<T>> {
+        if let Some(key) = key.downcast_ref::<K>() {
+            Ok(Box::new(self.lookup(key)))
+        } else {
+            Err(IndexError::KeyType)
+        }
+    }
+
+    fn matches(&self, value: &T, key: &dyn Any) -> Result<bool, IndexError<T>> {
+        let key = key.downcast_ref::<K>().ok_or(IndexError::KeyType)?;
+        Ok(&(self.map)(value) == key)
+    }
+
+    fn stats(&self) -> Option<IndexStats> {
+        Some(IndexStats {
+            keys: self.data.len(),
+            entries: self.data.len(),
+        })
+    }
+
+    fn snapshot(&self) -> Option<Box<dyn Index<T>>> {
+        Some(Box::new(UniqueHashIndex {
+            map: self.map.clone(),
+            data: self.data.clone(),
+        }))
+    }
+}
+
+impl<T, K, F, S> TypedIndex<T> for UniqueHashIndex<T, K, F, S>
+where
+    T: Identity + 'static,
+    K: Hash + Eq + Clone + 'static,
+    F: Fn(&T) -> K + 'static,
+    S: BuildHasher + 'static,
+{
+    type Key = K;
+}
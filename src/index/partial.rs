@@ -0,0 +1,177 @@
+use crate::index::{CompositeIndex, CountIndex, Index, IndexStats, SearchIndex, TypedIndex};
+use crate::Identity;
+use crate::IndexError;
+use std::any::Any;
+use std::ops::Bound;
+use std::sync::Arc;
+
+/// Index that only contains the elements matching a predicate, wrapping another index.
+///
+/// Elements for which the predicate returns false are left out of the wrapped index, so for
+/// example a [`UniqueBTreeIndex`](crate::UniqueBTreeIndex) wrapped this way only enforces
+/// uniqueness among the matching elements. When an element is updated, the old version is
+/// removed and the new one inserted as usual, so elements move in and out of the index as the
+/// predicate changes its mind. Lookups only find matching elements, and elements that do not
+/// match never match conditions of a [`Query`](crate::Query) on this index.
+pub struct PartialIndex<I, P> {
+    predicate: Arc<P>,
+    index: I,
+}
+
+impl<I, P> PartialIndex<I, P> {
+    pub fn new<T>(index: I, predicate: P) -> Self
+    where
+        T: Identity,
+        I: Index<T>,
+        P: Fn(&T) -> bool,
+    {
+        PartialIndex {
+            predicate: Arc::new(predicate),
+            index,
+        }
+    }
+}
+
+impl<T, I, P> Index<T> for PartialIndex<I, P>
+where
+    T: Identity + 'static,
+    I: Index<T>,
+    P: Fn(&T) -> bool + 'static,
+{
+    fn clear(&mut self) {
+        self.index.clear()
+    }
+
+    fn insert(&mut self, value: &T) -> Result<(), IndexError<T>> {
+        if (self.predicate)(value) {
+            self.index.insert(value)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn insert_bulk<'a>(
+        &mut self,
+        values: Box<dyn Iterator<Item = &'a T> + 'a>,
+    ) -> Result<(), IndexError<T>> {
+        let predicate = self.predicate.clone();
+        self.index
+            .insert_bulk(Box::new(values.filter(move |value| predicate(value))))
+    }
+
+    fn remove(&mut self, value: &T) -> Result<(), IndexError<T>> {
+        if (self.predicate)(value) {
+            self.index.remove(value)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn remove_bulk<'a>(
+        &mut self,
+        values: Box<dyn Iterator<Item = &'a T> + 'a>,
+    ) -> Result<(), IndexError<T>> {
+        let predicate = self.predicate.clone();
+        self.index
+            .remove_bulk(Box::new(values.filter(move |value| predicate(value))))
+    }
+
+    fn lookup(
+        &self,
+        key: &dyn Any,
+    ) -> Result<Box<dyn Iterator<Item = T::PrimaryKey> + '_>, IndexError<T>> {
+        self.index.lookup(key)
+    }
+
+    fn range(
+        &self,
+        start: Bound<&dyn Any>,
+        end: Bound<&dyn Any>,
+    ) -> Result<Box<dyn DoubleEndedIterator<Item = T::PrimaryKey> + '_>, IndexError<T>> {
+        self.index.range(start, end)
+    }
+
+    fn matches(&self, value: &T, key: &dyn Any) -> Result<bool, IndexError<T>> {
+        let matches = self.index.matches(value, key)?;
+        Ok(matches && (self.predicate)(value))
+    }
+
+    fn matches_range(
+        &self,
+        value: &T,
+        start: Bound<&dyn Any>,
+        end: Bound<&dyn Any>,
+    ) -> Result<bool, IndexError<T>> {
+        let matches = self.index.matches_range(value, start, end)?;
+        Ok(matches && (self.predicate)(value))
+    }
+
+    fn stats(&self) -> Option<IndexStats> {
+        self.index.stats()
+    }
+
+    fn snapshot(&self) -> Option<Box<dyn Index<T>>> {
+        let index: Box<dyn Any> = self.index.snapshot()?;
+        Some(Box::new(PartialIndex {
+            predicate: self.predicate.clone(),
+            index: *index.downcast::<I>().ok()?,
+        }))
+    }
+}
+
+impl<T, I, P> TypedIndex<T> for PartialIndex<I, P>
+where
+    T: Identity + 'static,
+    I: TypedIndex<T>,
+    P: Fn(&T) -> bool + 'static,
+{
+    type Key = I::Key;
+}
+
+impl<T, I, P> SearchIndex<T> for PartialIndex<I, P>
+where
+    T: Identity + 'static,
+    I: SearchIndex<T>,
+    P: Fn(&T) -> bool + 'static,
+{
+    fn search(&self, key: &I::Key) -> Vec<(T::PrimaryKey, f64)> {
+        self.index.search(key)
+    }
+}
+
+impl<T, I, P> CompositeIndex<T> for PartialIndex<I, P>
+where
+    T: Identity + 'static,
+    I: CompositeIndex<T>,
+    P: Fn(&T) -> bool + 'static,
+{
+    type Prefix = I::Prefix;
+    type Suffix = I::Suffix;
+
+    fn prefix(
+        &self,
+        prefix: &I::Prefix,
+    ) -> Box<dyn DoubleEndedIterator<Item = T::PrimaryKey> + '_> {
+        self.index.prefix(prefix)
+    }
+
+    fn prefix_range(
+        &self,
+        prefix: &I::Prefix,
+        start: Bound<&I::Suffix>,
+        end: Bound<&I::Suffix>,
+    ) -> Box<dyn DoubleEndedIterator<Item = T::PrimaryKey> + '_> {
+        self.index.prefix_range(prefix, start, end)
+    }
+}
+
+impl<T, I, P> CountIndex<T> for PartialIndex<I, P>
+where
+    T: Identity + 'static,
+    I: CountIndex<T>,
+    P: Fn(&T) -> bool + 'static,
+{
+    fn count(&self, key: &I::Key) -> usize {
+        self.index.count(key)
+    }
+}
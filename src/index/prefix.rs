@@ -0,0 +1,243 @@
+use crate::index::{CountIndex, Index, IndexStats, TypedIndex};
+use crate::Identity;
+use crate::IndexError;
+use im::{OrdMap, OrdSet};
+use std::any::Any;
+use std::str::Chars;
+use std::sync::Arc;
+
+/// Node of the trie, holding the elements whose string ends here.
+#[derive(Clone)]
+struct Node<K: Ord + Clone> {
+    /// Elements whose string ends at this node.
+    keys: OrdSet<K>,
+    /// Number of elements whose string ends at this node or below it.
+    count: usize,
+    children: OrdMap<char, Node<K>>,
+}
+
+impl<K: Ord + Clone> Default for Node<K> {
+    fn default() -> Self {
+        Node {
+            keys: Default::default(),
+            count: 0,
+            children: Default::default(),
+        }
+    }
+}
+
+impl<K: Ord + Clone> Node<K> {
+    /// Find the node for this prefix.
+    fn get(&self, prefix: &str) -> Option<&Node<K>> {
+        prefix
+            .chars()
+            .try_fold(self, |node, char| node.children.get(&char))
+    }
+
+    /// Insert an element below this node, returning if it was not there yet and whether its
+    /// string is new.
+    fn insert(&mut self, mut chars: Chars<'_>, key: K) -> (bool, bool) {
+        let (inserted, new) = match chars.next() {
+            Some(char) => self.children.entry(char).or_default().insert(chars, key),
+            None => (self.keys.insert(key).is_none(), self.keys.len() == 1),
+        };
+        if inserted {
+            self.count += 1;
+        }
+        (inserted, inserted && new)
+    }
+
+    /// Remove an element below this node, returning if it was there and whether its string is
+    /// gone.
+    fn remove(&mut self, mut chars: Chars<'_>, key: &K) -> (bool, bool) {
+        let (removed, gone) = match chars.next() {
+            Some(char) => match self.children.get_mut(&char) {
+                Some(child) => {
+                    let result = child.remove(chars, key);
+
+                    // remove the child altogether if no element is below it anymore
+                    if child.count == 0 {
+                        self.children.remove(&char);
+                    }
+                    result
+                }
+                None => (false, false),
+            },
+            None => (self.keys.remove(key).is_some(), self.keys.is_empty()),
+        };
+        if removed {
+            self.count -= 1;
+        }
+        (removed, removed && gone)
+    }
+}
+
+/// Iterator over the elements at and below a node, in lexicographic order of their strings.
+struct Iter<'a, K: Ord + Clone> {
+    keys: im::ordset::Iter<'a, K>,
+    children: Vec<im::ordmap::Iter<'a, char, Node<K>>>,
+}
+
+impl<'a, K: Ord + Clone> Iter<'a, K> {
+    fn new(node: &'a Node<K>) -> Self {
+        Iter {
+            keys: node.keys.iter(),
+            children: vec![node.children.iter()],
+        }
+    }
+}
+
+impl<'a, K: Ord + Clone> Iterator for Iter<'a, K> {
+    type Item = K;
+
+    fn next(&mut self) -> Option<K> {
+        loop {
+            if let Some(key) = self.keys.next() {
+                return Some(key.clone());
+            }
+            match self.children.last_mut()?.next() {
+                Some((_, node)) => {
+                    self.keys = node.keys.iter();
+                    self.children.push(node.children.iter());
+                }
+                None => {
+                    self.children.pop();
+                }
+            }
+        }
+    }
+}
+
+/// Index over a string of the elements, looked up by prefix.
+///
+/// Lookups with a `String` return all elements whose string starts with it, in lexicographic
+/// order of their strings and in primary key order for elements with the same string. The
+/// lookup is lazy, so taking the first few results of a short prefix is cheap, which makes this
+/// index suitable for autocompletion. Every node of the underlying trie knows how many elements
+/// are below it, so counting the elements with a prefix only takes as long as the prefix.
+pub struct PrefixIndex<T, F>
+where
+    T: Identity,
+    F: Fn(&T) -> String,
+{
+    map: Arc<F>,
+    root: Node<T::PrimaryKey>,
+    /// Number of distinct strings.
+    strings: usize,
+}
+
+impl<T, F> PrefixIndex<T, F>
+where
+    T: Identity,
+    F: Fn(&T) -> String,
+{
+    pub fn new(map: F) -> Self {
+        PrefixIndex {
+            map: Arc::new(map),
+            root: Default::default(),
+            strings: 0,
+        }
+    }
+
+    pub fn insert(&mut self, element: &T) -> Result<(), IndexError<T>> {
+        let string = (self.map)(element);
+        let (_, new) = self.root.insert(string.chars(), element.primary_key());
+        if new {
+            self.strings += 1;
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, element: &T) -> Result<(), IndexError<T>> {
+        let string = (self.map)(element);
+        let (_, gone) = self.root.remove(string.chars(), &element.primary_key());
+        if gone {
+            self.strings -= 1;
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.root = Default::default();
+        self.strings = 0;
+    }
+
+    /// Number of distinct strings, and of elements.
+    pub fn stats(&self) -> IndexStats {
+        IndexStats {
+            keys: self.strings,
+            entries: self.root.count,
+        }
+    }
+
+    /// Lookup all keys of elements whose string starts with the prefix, in lexicographic order.
+    pub fn lookup(&self, prefix: &str) -> impl Iterator<Item = T::PrimaryKey> + '_ {
+        self.root.get(prefix).map(Iter::new).into_iter().flatten()
+    }
+
+    /// Number of elements whose string starts with the prefix.
+    pub fn count(&self, prefix: &str) -> usize {
+        self.root.get(prefix).map(|node| node.count).unwrap_or(0)
+    }
+}
+
+impl<T, F> Index<T> for PrefixIndex<T, F>
+where
+    T: Identity + 'static,
+    F: Fn(&T) -> String + 'static,
+{
+    fn clear(&mut self) {
+        self.clear()
+    }
+
+    fn insert(&mut self, value: &T) -> Result<(), IndexError<T>> {
+        self.insert(value)
+    }
+
+    fn remove(&mut self, value: &T) -> Result<(), IndexError<T>> {
+        self.remove(value)
+    }
+
+    fn lookup(
+        &self,
+        key: &dyn Any,
+    ) -> Result<Box<dyn Iterator<Item = T::PrimaryKey> + '_>, IndexError<T>> {
+        let prefix = key.downcast_ref::<String>().ok_or(IndexError::KeyType)?;
+        Ok(Box::new(self.lookup(prefix)))
+    }
+
+    fn matches(&self, value: &T, key: &dyn Any) -> Result<bool, IndexError<T>> {
+        let prefix = key.downcast_ref::<String>().ok_or(IndexError::KeyType)?;
+        Ok((self.map)(value).starts_with(prefix.as_str()))
+    }
+
+    fn stats(&self) -> Option<IndexStats> {
+        Some(self.stats())
+    }
+
+    fn snapshot(&self) -> Option<Box<dyn Index<T>>> {
+        Some(Box::new(PrefixIndex {
+            map: self.map.clone(),
+            root: self.root.clone(),
+            strings: self.strings,
+        }))
+    }
+}
+
+impl<T, F> TypedIndex<T> for PrefixIndex<T, F>
+where
+    T: Identity + 'static,
+    F: Fn(&T) -> String + 'static,
+{
+    type Key = String;
+}
+
+impl<T, F> CountIndex<T> for PrefixIndex<T, F>
+where
+    T: Identity + 'static,
+    F: Fn(&T) -> String + 'static,
+{
+    fn count(&self, prefix: &String) -> usize {
+        self.count(prefix)
+    }
+}
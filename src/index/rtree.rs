@@ -0,0 +1,261 @@
+use crate::index::{Index, TypedIndex};
+use crate::Identity;
+use crate::IndexError;
+use rstar::{Point, PointDistance, RTree, RTreeObject, AABB};
+use std::any::Any;
+
+/// Axis-aligned box in `D` dimensions, the key of an [`RTreeIndex`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox<const D: usize> {
+    pub min: [f64; D],
+    pub max: [f64; D],
+}
+
+impl<const D: usize> BoundingBox<D> {
+    /// Box spanned by two opposite corners, in any order.
+    pub fn new(a: [f64; D], b: [f64; D]) -> Self {
+        let mut min = a;
+        let mut max = b;
+        for axis in 0..D {
+            if min[axis] > max[axis] {
+                std::mem::swap(&mut min[axis], &mut max[axis]);
+            }
+        }
+        BoundingBox { min, max }
+    }
+
+    /// Box containing only this point.
+    pub fn point(point: [f64; D]) -> Self {
+        BoundingBox {
+            min: point,
+            max: point,
+        }
+    }
+
+    /// Determine if this box and the other one share any point.
+    pub fn intersects(&self, other: &BoundingBox<D>) -> bool {
+        (0..D).all(|axis| self.min[axis] <= other.max[axis] && other.min[axis] <= self.max[axis])
+    }
+
+    /// Determine if all coordinates of this box are finite, meaning neither NaN nor infinite.
+    pub fn is_finite(&self) -> bool {
+        self.min
+            .iter()
+            .chain(&self.max)
+            .all(|coordinate| coordinate.is_finite())
+    }
+
+    fn envelope(&self) -> AABB<[f64; D]> {
+        AABB::from_corners(self.min, self.max)
+    }
+}
+
+/// Query on an [`RTreeIndex`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpatialQuery<const D: usize> {
+    /// Elements whose box intersects this one.
+    Intersects(BoundingBox<D>),
+    /// The given number of elements closest to this point, closest first.
+    Nearest([f64; D], usize),
+}
+
+/// Element stored in the tree, its box along with its primary key.
+#[derive(Debug, Clone, PartialEq)]
+struct Entry<K, const D: usize> {
+    envelope: AABB<[f64; D]>,
+    key: K,
+}
+
+impl<K, const D: usize> RTreeObject for Entry<K, D>
+where
+    [f64; D]: Point<Scalar = f64>,
+{
+    type Envelope = AABB<[f64; D]>;
+
+    fn envelope(&self) -> Self::Envelope {
+        self.envelope
+    }
+}
+
+impl<K, const D: usize> PointDistance for Entry<K, D>
+where
+    [f64; D]: Point<Scalar = f64>,
+{
+    fn distance_2(&self, point: &[f64; D]) -> f64 {
+        self.envelope.distance_2(point)
+    }
+}
+
+/// Spatial index over points or boxes in `D` dimensions, backed by an R-tree.
+///
+/// Every element is mapped to a [`BoundingBox`], use [`BoundingBox::point`] for elements that
+/// are a single point. The index is looked up with a [`SpatialQuery`], finding the elements
+/// whose box intersects a given one in no particular order, or the elements nearest to a point
+/// in order of distance. The distance to a box is the distance to its closest point.
+///
+/// Boxes must have finite coordinates, inserting an element whose box has a NaN or infinite
+/// coordinate fails with [`IndexError::InvalidKey`]. Nearest lookups of such a point find
+/// nothing.
+///
+/// The tree is not a persistent data structure, so this index cannot be queried through
+/// snapshots.
+pub struct RTreeIndex<T: Identity, const D: usize, F: Fn(&T) -> BoundingBox<D>>
+where
+    [f64; D]: Point<Scalar = f64>,
+{
+    map: F,
+    tree: RTree<Entry<T::PrimaryKey, D>>,
+}
+
+impl<T, const D: usize, F> RTreeIndex<T, D, F>
+where
+    T: Identity,
+    F: Fn(&T) -> BoundingBox<D>,
+    [f64; D]: Point<Scalar = f64>,
+{
+    pub fn new(map: F) -> Self {
+        RTreeIndex {
+            map,
+            tree: RTree::new(),
+        }
+    }
+
+    /// Entry of an element in the tree, failing if its box is not finite.
+    fn entry(&self, element: &T) -> Result<Entry<T::PrimaryKey, D>, IndexError<T>> {
+        let area = (self.map)(element);
+        if !area.is_finite() {
+            return Err(IndexError::InvalidKey);
+        }
+        Ok(Entry {
+            envelope: area.envelope(),
+            key: element.primary_key(),
+        })
+    }
+
+    pub fn insert(&mut self, element: &T) -> Result<(), IndexError<T>> {
+        let entry = self.entry(element)?;
+        self.tree.insert(entry);
+        Ok(())
+    }
+
+    /// Insert multiple elements, building the tree in one go if it is empty.
+    pub fn insert_bulk<'a>(
+        &mut self,
+        elements: impl IntoIterator<Item = &'a T>,
+    ) -> Result<(), IndexError<T>>
+    where
+        T: 'a,
+    {
+        let entries = elements
+            .into_iter()
+            .map(|element| self.entry(element))
+            .collect::<Result<Vec<_>, _>>()?;
+        if self.tree.size() == 0 {
+            self.tree = RTree::bulk_load(entries);
+        } else {
+            for entry in entries {
+                self.tree.insert(entry);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, element: &T) -> Result<(), IndexError<T>> {
+        // elements whose box is not finite were never inserted.
+        if let Ok(entry) = self.entry(element) {
+            self.tree.remove(&entry);
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.tree = RTree::new();
+    }
+
+    /// Lookup all keys of elements whose box intersects this one.
+    pub fn intersecting(&self, area: &BoundingBox<D>) -> impl Iterator<Item = T::PrimaryKey> + '_ {
+        self.tree
+            .locate_in_envelope_intersecting(&area.envelope())
+            .map(|entry| entry.key.clone())
+    }
+
+    /// Lookup the keys of the `count` elements nearest to the point, nearest first.
+    pub fn nearest(
+        &self,
+        point: &[f64; D],
+        count: usize,
+    ) -> impl Iterator<Item = T::PrimaryKey> + '_ {
+        let finite = point.iter().all(|coordinate| coordinate.is_finite());
+        finite
+            .then(|| self.tree.nearest_neighbor_iter(point))
+            .into_iter()
+            .flatten()
+            .take(count)
+            .map(|entry| entry.key.clone())
+    }
+
+    pub fn lookup(&self, query: &SpatialQuery<D>) -> Box<dyn Iterator<Item = T::PrimaryKey> + '_> {
+        match query {
+            SpatialQuery::Intersects(area) => Box::new(self.intersecting(area)),
+            SpatialQuery::Nearest(point, count) => Box::new(self.nearest(point, *count)),
+        }
+    }
+}
+
+impl<T, const D: usize, F> Index<T> for RTreeIndex<T, D, F>
+where
+    T: Identity + 'static,
+    F: Fn(&T) -> BoundingBox<D> + 'static,
+    [f64; D]: Point<Scalar = f64>,
+{
+    fn clear(&mut self) {
+        self.clear()
+    }
+
+    fn insert(&mut self, value: &T) -> Result<(), IndexError<T>> {
+        self.insert(value)
+    }
+
+    fn insert_bulk<'a>(
+        &mut self,
+        values: Box<dyn Iterator<Item = &'a T> + 'a>,
+    ) -> Result<(), IndexError<T>> {
+        self.insert_bulk(values)
+    }
+
+    fn remove(&mut self, value: &T) -> Result<(), IndexError<T>> {
+        self.remove(value)
+    }
+
+    fn lookup(
+        &self,
+        key: &dyn Any,
+    ) -> Result<Box<dyn Iterator<Item = T::PrimaryKey> + '_>, IndexError<T>> {
+        let query = key
+            .downcast_ref::<SpatialQuery<D>>()
+            .ok_or(IndexError::KeyType)?;
+        Ok(self.lookup(query))
+    }
+
+    fn matches(&self, value: &T, key: &dyn Any) -> Result<bool, IndexError<T>> {
+        let query = key
+            .downcast_ref::<SpatialQuery<D>>()
+            .ok_or(IndexError::KeyType)?;
+        match query {
+            SpatialQuery::Intersects(area) => Ok((self.map)(value).intersects(area)),
+            SpatialQuery::Nearest(point, count) => {
+                let key = value.primary_key();
+                Ok(self.nearest(point, *count).any(|nearest| nearest == key))
+            }
+        }
+    }
+}
+
+impl<T, const D: usize, F> TypedIndex<T> for RTreeIndex<T, D, F>
+where
+    T: Identity + 'static,
+    F: Fn(&T) -> BoundingBox<D> + 'static,
+    [f64; D]: Point<Scalar = f64>,
+{
+    type Key = SpatialQuery<D>;
+}
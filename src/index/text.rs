@@ -0,0 +1,328 @@
+use crate::index::{Index, IndexStats, SearchIndex, TypedIndex};
+use crate::Identity;
+use crate::IndexError;
+use im::OrdMap;
+use std::any::Any;
+use std::collections::*;
+use std::sync::Arc;
+
+mod tokenizer;
+
+pub use tokenizer::{Lowercase, Stem, Tokenizer, Whitespace};
+
+/// Controls how quickly repeating a term stops raising the score, for BM25.
+const K1: f64 = 1.2;
+
+/// Controls how much longer texts are penalized, for BM25.
+const B: f64 = 0.75;
+
+/// Query on a [`TextIndex`], the text is split into terms by the tokenizer of the index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextQuery {
+    /// Elements containing all terms of the text.
+    All(String),
+    /// Elements containing any term of the text.
+    Any(String),
+    /// Elements containing the terms of the text next to each other, in order.
+    Phrase(String),
+}
+
+impl TextQuery {
+    pub fn all(text: impl Into<String>) -> Self {
+        TextQuery::All(text.into())
+    }
+
+    pub fn any(text: impl Into<String>) -> Self {
+        TextQuery::Any(text.into())
+    }
+
+    pub fn phrase(text: impl Into<String>) -> Self {
+        TextQuery::Phrase(text.into())
+    }
+}
+
+/// Full-text index over one or more string fields of the elements.
+///
+/// The fields are split into terms by a [`Tokenizer`], and every term is mapped to the elements
+/// containing it along with the positions it occurs at. Lookups with a [`TextQuery`] return the
+/// matching elements in primary key order, searches through
+/// [`IndexHandle::search`](crate::IndexHandle::search) rank them by their BM25 score. Phrases do
+/// not match across fields.
+pub struct TextIndex<T, F, Z = Lowercase>
+where
+    T: Identity,
+    F: Fn(&T) -> Vec<String>,
+    Z: Tokenizer,
+{
+    map: Arc<F>,
+    tokenizer: Arc<Z>,
+    /// Positions of every term in every element containing it.
+    terms: OrdMap<String, OrdMap<T::PrimaryKey, Vec<usize>>>,
+    /// Number of terms in every element.
+    lengths: OrdMap<T::PrimaryKey, usize>,
+    /// Number of terms in all elements.
+    length: usize,
+    /// Number of pairs of a term and an element containing it.
+    entries: usize,
+}
+
+impl<T: Identity, F: Fn(&T) -> Vec<String>> TextIndex<T, F> {
+    pub fn new(map: F) -> Self {
+        TextIndex::with_tokenizer(map, Lowercase::default())
+    }
+}
+
+impl<T, F, Z> TextIndex<T, F, Z>
+where
+    T: Identity,
+    F: Fn(&T) -> Vec<String>,
+    Z: Tokenizer,
+{
+    pub fn with_tokenizer(map: F, tokenizer: Z) -> Self {
+        TextIndex {
+            map: Arc::new(map),
+            tokenizer: Arc::new(tokenizer),
+            terms: Default::default(),
+            lengths: Default::default(),
+            length: 0,
+            entries: 0,
+        }
+    }
+
+    /// Split the fields of an element into terms, along with the positions they occur at.
+    fn tokenize(&self, element: &T) -> (BTreeMap<String, Vec<usize>>, usize) {
+        let mut terms: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        let mut length = 0;
+        let mut position = 0;
+        for field in (self.map)(element) {
+            for term in self.tokenizer.tokenize(&field) {
+                terms.entry(term).or_default().push(position);
+                position += 1;
+                length += 1;
+            }
+
+            // leave a gap between fields, so phrases cannot span them.
+            position += 1;
+        }
+        (terms, length)
+    }
+
+    pub fn insert(&mut self, element: &T) -> Result<(), IndexError<T>> {
+        let key = element.primary_key();
+        let (terms, length) = self.tokenize(element);
+        for (term, positions) in terms {
+            if self
+                .terms
+                .entry(term)
+                .or_default()
+                .insert(key.clone(), positions)
+                .is_none()
+            {
+                self.entries += 1;
+            }
+        }
+        if let Some(previous) = self.lengths.insert(key, length) {
+            self.length -= previous;
+        }
+        self.length += length;
+        Ok(())
+    }
+
+    pub fn remove(&mut self, element: &T) -> Result<(), IndexError<T>> {
+        let key = element.primary_key();
+        let (terms, _) = self.tokenize(element);
+        for term in terms.into_keys() {
+            if let im::ordmap::Entry::Occupied(mut entry) = self.terms.entry(term) {
+                if entry.get_mut().remove(&key).is_some() {
+                    self.entries -= 1;
+                }
+
+                // remove the term altogether if no element contains it anymore
+                if entry.get().is_empty() {
+                    entry.remove();
+                }
+            }
+        }
+        if let Some(length) = self.lengths.remove(&key) {
+            self.length -= length;
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.terms.clear();
+        self.lengths.clear();
+        self.length = 0;
+        self.entries = 0;
+    }
+
+    /// Number of distinct terms, and of pairs of a term and an element containing it.
+    pub fn stats(&self) -> IndexStats {
+        IndexStats {
+            keys: self.terms.len(),
+            entries: self.entries,
+        }
+    }
+
+    /// Lookup all keys of elements matching the query, in key order.
+    pub fn lookup(&self, query: &TextQuery) -> impl Iterator<Item = T::PrimaryKey> {
+        self.matches(query).1.into_iter()
+    }
+
+    /// Lookup all keys of elements matching the query along with their score, best first.
+    ///
+    /// Elements are scored with BM25 on the terms of the query, elements with the same score are
+    /// in key order.
+    pub fn search(&self, query: &TextQuery) -> Vec<(T::PrimaryKey, f64)> {
+        let (terms, keys) = self.matches(query);
+        let terms: BTreeSet<String> = terms.into_iter().collect();
+        let mut results: Vec<(T::PrimaryKey, f64)> = keys
+            .into_iter()
+            .map(|key| {
+                let score = self.score(&terms, &key);
+                (key, score)
+            })
+            .collect();
+        results.sort_by(|(_, left), (_, right)| right.total_cmp(left));
+        results
+    }
+
+    /// Determine the terms of the query and the keys of the elements matching it.
+    fn matches(&self, query: &TextQuery) -> (Vec<String>, BTreeSet<T::PrimaryKey>) {
+        let (text, all) = match query {
+            TextQuery::All(text) | TextQuery::Phrase(text) => (text, true),
+            TextQuery::Any(text) => (text, false),
+        };
+        let terms = self.tokenizer.tokenize(text);
+        let mut postings = Vec::new();
+        for term in &terms {
+            match self.terms.get(term) {
+                Some(elements) => postings.push(elements),
+                None if all => return (terms, BTreeSet::new()),
+                None => {}
+            }
+        }
+
+        let keys: BTreeSet<T::PrimaryKey> = if all {
+            // go through the rarest term, checking the others for every element containing it.
+            postings.sort_by_key(|elements| elements.len());
+            let Some((rarest, others)) = postings.split_first() else {
+                return (terms, BTreeSet::new());
+            };
+            rarest
+                .keys()
+                .filter(|key| others.iter().all(|elements| elements.contains_key(key)))
+                .cloned()
+                .collect()
+        } else {
+            postings
+                .iter()
+                .flat_map(|elements| elements.keys().cloned())
+                .collect()
+        };
+
+        let keys = match query {
+            TextQuery::Phrase(_) => keys
+                .into_iter()
+                .filter(|key| self.phrase_at(&terms, key))
+                .collect(),
+            _ => keys,
+        };
+        (terms, keys)
+    }
+
+    /// Determine if the terms occur in this order, next to each other, in the element.
+    ///
+    /// The element must contain all of the terms.
+    fn phrase_at(&self, terms: &[String], key: &T::PrimaryKey) -> bool {
+        let positions = |term: &String| &self.terms[term][key];
+        let Some((first, rest)) = terms.split_first() else {
+            return false;
+        };
+        positions(first).iter().any(|start| {
+            rest.iter()
+                .enumerate()
+                .all(|(offset, term)| positions(term).binary_search(&(start + offset + 1)).is_ok())
+        })
+    }
+
+    /// Score an element containing some of the terms with BM25.
+    fn score(&self, terms: &BTreeSet<String>, key: &T::PrimaryKey) -> f64 {
+        let count = self.lengths.len() as f64;
+        let average = self.length as f64 / count;
+        let length = self.lengths.get(key).copied().unwrap_or_default() as f64;
+        terms
+            .iter()
+            .filter_map(|term| {
+                let elements = self.terms.get(term)?;
+                let frequency = elements.get(key)?.len() as f64;
+                let containing = elements.len() as f64;
+                let rarity = ((count - containing + 0.5) / (containing + 0.5) + 1.0).ln();
+                let saturation = K1 * (1.0 - B + B * length / average);
+                Some(rarity * frequency * (K1 + 1.0) / (frequency + saturation))
+            })
+            .sum()
+    }
+}
+
+impl<T, F, Z> Index<T> for TextIndex<T, F, Z>
+where
+    T: Identity + 'static,
+    F: Fn(&T) -> Vec<String> + 'static,
+    Z: Tokenizer + 'static,
+{
+    fn clear(&mut self) {
+        self.clear()
+    }
+
+    fn insert(&mut self, value: &T) -> Result<(), IndexError<T>> {
+        self.insert(value)
+    }
+
+    fn remove(&mut self, value: &T) -> Result<(), IndexError<T>> {
+        self.remove(value)
+    }
+
+    fn lookup(
+        &self,
+        key: &dyn Any,
+    ) -> Result<Box<dyn Iterator<Item = T::PrimaryKey> + '_>, IndexError<T>> {
+        let query = key.downcast_ref::<TextQuery>().ok_or(IndexError::KeyType)?;
+        Ok(Box::new(self.lookup(query)))
+    }
+
+    fn stats(&self) -> Option<IndexStats> {
+        Some(self.stats())
+    }
+
+    fn snapshot(&self) -> Option<Box<dyn Index<T>>> {
+        Some(Box::new(TextIndex {
+            map: self.map.clone(),
+            tokenizer: self.tokenizer.clone(),
+            terms: self.terms.clone(),
+            lengths: self.lengths.clone(),
+            length: self.length,
+            entries: self.entries,
+        }))
+    }
+}
+
+impl<T, F, Z> TypedIndex<T> for TextIndex<T, F, Z>
+where
+    T: Identity + 'static,
+    F: Fn(&T) -> Vec<String> + 'static,
+    Z: Tokenizer + 'static,
+{
+    type Key = TextQuery;
+}
+
+impl<T, F, Z> SearchIndex<T> for TextIndex<T, F, Z>
+where
+    T: Identity + 'static,
+    F: Fn(&T) -> Vec<String> + 'static,
+    Z: Tokenizer + 'static,
+{
+    fn search(&self, query: &TextQuery) -> Vec<(T::PrimaryKey, f64)> {
+        self.search(query)
+    }
+}
@@ -0,0 +1,99 @@
+/// Splits text into the terms that are indexed and searched for.
+///
+/// The same tokenizer is used for the indexed text and for queries, so a query finds the
+/// elements containing the same terms after normalization. Closures taking the text and
+/// returning the terms are tokenizers as well.
+pub trait Tokenizer {
+    /// Split the text into terms, in the order they appear.
+    fn tokenize(&self, text: &str) -> Vec<String>;
+}
+
+impl<F: Fn(&str) -> Vec<String>> Tokenizer for F {
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        self(text)
+    }
+}
+
+/// Tokenizer splitting text on whitespace, stripping punctuation around the words.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Whitespace;
+
+impl Tokenizer for Whitespace {
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        text.split_whitespace()
+            .map(|word| word.trim_matches(|c: char| !c.is_alphanumeric()))
+            .filter(|word| !word.is_empty())
+            .map(String::from)
+            .collect()
+    }
+}
+
+/// Tokenizer turning the terms of another tokenizer into lowercase.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Lowercase<Z = Whitespace>(pub Z);
+
+impl<Z: Tokenizer> Tokenizer for Lowercase<Z> {
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        let mut terms = self.0.tokenize(text);
+        for term in &mut terms {
+            *term = term.to_lowercase();
+        }
+        terms
+    }
+}
+
+/// Tokenizer reducing the terms of another tokenizer to their stem.
+///
+/// This is a light stemmer for English, which strips plural and verb endings so that for example
+/// "indexes", "indexed" and "indexing" all become "index". It does not know about irregular
+/// words, and expects lowercase terms.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stem<Z = Lowercase>(pub Z);
+
+impl<Z: Tokenizer> Tokenizer for Stem<Z> {
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        let mut terms = self.0.tokenize(text);
+        for term in &mut terms {
+            *term = stem(term);
+        }
+        terms
+    }
+}
+
+/// Strip the plural and verb endings of an English word.
+fn stem(word: &str) -> String {
+    let has_vowel = |stem: &str| stem.chars().any(|c| "aeiouy".contains(c));
+
+    // plurals, keeping words like "class" and "bus" intact.
+    let word = if let Some(stem) = word.strip_suffix("sses") {
+        format!("{stem}ss")
+    } else if let Some(stem) = word.strip_suffix("ies") {
+        format!("{stem}y")
+    } else if let Some(stem) = word.strip_suffix("xes") {
+        format!("{stem}x")
+    } else if word.ends_with("ss") || word.ends_with("us") || word.ends_with("is") {
+        word.to_string()
+    } else if let Some(stem) = word.strip_suffix('s') {
+        stem.to_string()
+    } else {
+        word.to_string()
+    };
+
+    // verb endings, only if a syllable remains.
+    let stem = ["ing", "ed"]
+        .iter()
+        .filter_map(|suffix| word.strip_suffix(suffix))
+        .find(|stem| stem.len() >= 3 && has_vowel(stem));
+    let Some(stem) = stem else {
+        return word;
+    };
+
+    // undouble the final consonant, as in "running".
+    let mut chars = stem.chars().rev();
+    match (chars.next(), chars.next()) {
+        (Some(last), Some(before)) if last == before && !"aeiouylsz".contains(last) => {
+            stem[..stem.len() - last.len_utf8()].to_string()
+        }
+        _ => stem.to_string(),
+    }
+}
@@ -1,10 +1,29 @@
+mod concurrent;
 mod error;
 mod index;
+mod query;
 pub mod table;
 #[cfg(test)]
 mod tests;
+#[cfg(feature = "wal")]
+mod wal;
 
-pub use crate::index::{BTreeIndex, Index, UniqueBTreeIndex};
-pub use crate::table::Identity;
-pub use crate::table::Table;
+pub use crate::concurrent::ConcurrentTable;
+pub use crate::index::{
+    BTreeIndex, CompositeBTreeIndex, CompositeIndex, CountIndex, HashIndex, Index, IndexHandle,
+    IndexStats, Lowercase, MultiBTreeIndex, PartialIndex, PrefixIndex, SearchIndex, SharedIndex,
+    Stem, TextIndex, TextQuery, Tokenizer, TypedIndex, UniqueBTreeIndex, UniqueHashIndex,
+    UniqueMultiBTreeIndex, Whitespace,
+};
+#[cfg(feature = "rtree")]
+pub use crate::index::{BoundingBox, RTreeIndex, SpatialQuery};
+pub use crate::query::{Conditions, Query};
+pub use crate::table::{
+    Callback, Change, Identity, Local, OnRemove, RemovedCallback, Shared, Threading,
+};
+pub use crate::table::{IterMut, RowMut, Snapshot, Table, View};
+#[cfg(feature = "wal")]
+pub use crate::wal::DurableTable;
+#[cfg(feature = "wal")]
+pub use error::WalError;
 pub use error::{IndexError, TableError};
@@ -0,0 +1,419 @@
+use crate::{Identity, IndexError, IndexHandle, TableError, View};
+use std::any::Any;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::ops::{Bound, RangeBounds};
+
+type Filter<'a, T> = Box<dyn Fn(&T) -> bool + 'a>;
+
+/// Condition on the key of an element in an index.
+enum Condition {
+    /// Key equals this one.
+    Eq(String, Box<dyn Any>),
+    /// Key is within these bounds.
+    Range(String, Bound<Box<dyn Any>>, Bound<Box<dyn Any>>),
+    /// Nested conditions.
+    Nested(Conditions),
+}
+
+impl Condition {
+    /// Plan how to resolve this condition, based on the statistics of its index.
+    fn plan<T: Identity>(&self, view: &dyn View<T>) -> Result<Plan<'_>, TableError<T>> {
+        match self {
+            Condition::Eq(name, _) => {
+                let estimate = view
+                    .index(name)?
+                    .stats()
+                    .map(|stats| stats.average().ceil() as usize);
+                Ok(Plan::Probe(self, estimate))
+            }
+            Condition::Range(name, start, end) => {
+                // without knowing how keys are distributed, every bound is assumed to keep a
+                // third of the entries.
+                let bounded = [start, end]
+                    .iter()
+                    .filter(|bound| !matches!(bound, Bound::Unbounded))
+                    .count();
+                let estimate = view
+                    .index(name)?
+                    .stats()
+                    .map(|stats| stats.entries / 3usize.pow(bounded as u32));
+                Ok(Plan::Probe(self, estimate))
+            }
+            Condition::Nested(conditions) => conditions.plan(view),
+        }
+    }
+
+    /// Look up the primary keys of the elements matching a condition on a single index.
+    fn lookup<T: Identity>(
+        &self,
+        view: &dyn View<T>,
+    ) -> Result<BTreeSet<T::PrimaryKey>, TableError<T>> {
+        let (name, keys) = match self {
+            Condition::Eq(name, key) => (
+                name,
+                view.index(name)?
+                    .lookup(key.as_ref())
+                    .map(Iterator::collect),
+            ),
+            Condition::Range(name, start, end) => (
+                name,
+                view.index(name)?
+                    .range(
+                        start.as_ref().map(Box::as_ref),
+                        end.as_ref().map(Box::as_ref),
+                    )
+                    .map(Iterator::collect),
+            ),
+            Condition::Nested(_) => unreachable!("nested conditions are planned, not probed"),
+        };
+        keys.map_err(|error| Condition::error(name, error))
+    }
+
+    /// Determine if an element matches this condition.
+    fn matches<T: Identity>(&self, view: &dyn View<T>, element: &T) -> Result<bool, TableError<T>> {
+        let (name, matches) = match self {
+            Condition::Eq(name, key) => (name, view.index(name)?.matches(element, key.as_ref())),
+            Condition::Range(name, start, end) => (
+                name,
+                view.index(name)?.matches_range(
+                    element,
+                    start.as_ref().map(Box::as_ref),
+                    end.as_ref().map(Box::as_ref),
+                ),
+            ),
+            Condition::Nested(conditions) => return conditions.matches(view, element),
+        };
+        matches.map_err(|error| Condition::error(name, error))
+    }
+
+    /// Map errors of an index, keys come from a typed handle, so a key type mismatch means the
+    /// index is gone.
+    fn error<T: Identity>(name: &str, error: IndexError<T>) -> TableError<T> {
+        match error {
+            IndexError::KeyType => TableError::UnknownIndex(name.to_string()),
+            error => TableError::index(name, error),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Eq(name, _) => write!(f, "{name} = ?"),
+            Condition::Range(name, _, _) => write!(f, "{name} in range"),
+            Condition::Nested(conditions) => write!(f, "({conditions})"),
+        }
+    }
+}
+
+/// Conditions on indices of which either all or any must hold.
+///
+/// Used to build nested conditions for [`Query::all`] and [`Query::any`].
+pub struct Conditions {
+    all: bool,
+    conditions: Vec<Condition>,
+}
+
+impl Conditions {
+    fn new(all: bool) -> Self {
+        Conditions {
+            all,
+            conditions: Vec::new(),
+        }
+    }
+
+    /// Add a condition that the key in the index equals `key`.
+    pub fn eq<T: Identity, K: Any, I: ?Sized>(
+        mut self,
+        index: &IndexHandle<T, K, I>,
+        key: impl Into<K>,
+    ) -> Self {
+        let key: Box<dyn Any> = Box::new(key.into());
+        self.conditions
+            .push(Condition::Eq(index.name().to_string(), key));
+        self
+    }
+
+    /// Add a condition that the key in the index is within the range.
+    pub fn range<T: Identity, K: Any + Clone, I: ?Sized>(
+        mut self,
+        index: &IndexHandle<T, K, I>,
+        range: impl RangeBounds<K>,
+    ) -> Self {
+        let boxed = |key: &K| Box::new(key.clone()) as Box<dyn Any>;
+        self.conditions.push(Condition::Range(
+            index.name().to_string(),
+            range.start_bound().map(boxed),
+            range.end_bound().map(boxed),
+        ));
+        self
+    }
+
+    /// Add a condition that all of the nested conditions hold.
+    pub fn all(mut self, conditions: impl FnOnce(Conditions) -> Conditions) -> Self {
+        let conditions = conditions(Conditions::new(true));
+        self.conditions.push(Condition::Nested(conditions));
+        self
+    }
+
+    /// Add a condition that any of the nested conditions holds.
+    pub fn any(mut self, conditions: impl FnOnce(Conditions) -> Conditions) -> Self {
+        let conditions = conditions(Conditions::new(false));
+        self.conditions.push(Condition::Nested(conditions));
+        self
+    }
+
+    /// Plan how to resolve these conditions.
+    ///
+    /// When all conditions must hold, the one estimated to match the fewest elements drives the
+    /// plan, and the others are checked on the elements it finds.
+    fn plan<T: Identity>(&self, view: &dyn View<T>) -> Result<Plan<'_>, TableError<T>> {
+        let mut plans = Vec::new();
+        for condition in &self.conditions {
+            match condition.plan(view)? {
+                // a condition matching everything makes no difference when all must hold.
+                Plan::Scan if self.all => {}
+                Plan::Scan => return Ok(Plan::Scan),
+                plan => plans.push((condition, plan)),
+            }
+        }
+
+        if !self.all {
+            return Ok(Plan::Union(
+                plans.into_iter().map(|(_, plan)| plan).collect(),
+            ));
+        }
+        let driver = plans
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, (_, plan))| plan.estimate())
+            .map(|(position, _)| position);
+        let Some(driver) = driver else {
+            return Ok(Plan::Scan);
+        };
+        let (_, driver) = plans.remove(driver);
+        if plans.is_empty() {
+            return Ok(driver);
+        }
+        let checks = plans.into_iter().map(|(condition, _)| condition).collect();
+        Ok(Plan::Filter(Box::new(driver), checks))
+    }
+
+    /// Determine if an element matches these conditions.
+    fn matches<T: Identity>(&self, view: &dyn View<T>, element: &T) -> Result<bool, TableError<T>> {
+        for condition in &self.conditions {
+            if condition.matches(view, element)? != self.all {
+                return Ok(!self.all);
+            }
+        }
+        Ok(self.all)
+    }
+}
+
+impl fmt::Display for Conditions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let separator = if self.all { " and " } else { " or " };
+        for (position, condition) in self.conditions.iter().enumerate() {
+            if position > 0 {
+                f.write_str(separator)?;
+            }
+            write!(f, "{condition}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Plan for resolving conditions into the primary keys of the matching elements.
+enum Plan<'c> {
+    /// Every element matches, so all of them are scanned.
+    Scan,
+    /// Look up a single condition in its index, with the estimated number of elements found if
+    /// the index keeps statistics.
+    Probe(&'c Condition, Option<usize>),
+    /// Resolve the driving plan, keeping the elements that match all of the conditions.
+    Filter(Box<Plan<'c>>, Vec<&'c Condition>),
+    /// Merge the elements found by all plans.
+    Union(Vec<Plan<'c>>),
+}
+
+impl Plan<'_> {
+    /// Estimated number of elements found, indices without statistics count as finding all.
+    fn estimate(&self) -> usize {
+        match self {
+            Plan::Scan | Plan::Probe(_, None) => usize::MAX,
+            Plan::Probe(_, Some(estimate)) => *estimate,
+            Plan::Filter(driver, _) => driver.estimate(),
+            Plan::Union(plans) => plans
+                .iter()
+                .fold(0, |total, plan| total.saturating_add(plan.estimate())),
+        }
+    }
+
+    /// Resolve this plan into the set of matching primary keys.
+    ///
+    /// Returns nothing if every element matches, in which case all of them have to be scanned.
+    fn keys<T: Identity>(
+        &self,
+        view: &dyn View<T>,
+    ) -> Result<Option<BTreeSet<T::PrimaryKey>>, TableError<T>> {
+        match self {
+            Plan::Scan => Ok(None),
+            Plan::Probe(condition, _) => condition.lookup(view).map(Some),
+            Plan::Filter(driver, checks) => {
+                let mut keys = BTreeSet::new();
+                'keys: for key in driver.keys(view)?.unwrap_or_default() {
+                    let Some(element) = view.lookup(&key) else {
+                        continue;
+                    };
+                    for check in checks {
+                        if !check.matches(view, element)? {
+                            continue 'keys;
+                        }
+                    }
+                    keys.insert(key);
+                }
+                Ok(Some(keys))
+            }
+            Plan::Union(plans) => {
+                let mut keys = BTreeSet::new();
+                for plan in plans {
+                    keys.append(&mut plan.keys(view)?.unwrap_or_default());
+                }
+                Ok(Some(keys))
+            }
+        }
+    }
+
+    /// Describe this plan, one step per line with the steps it depends on indented below.
+    fn explain(&self, depth: usize, lines: &mut Vec<String>) {
+        let indent = "  ".repeat(depth);
+        match self {
+            Plan::Scan => lines.push(format!("{indent}scan")),
+            Plan::Probe(condition, Some(estimate)) => lines.push(format!(
+                "{indent}probe {condition} (estimated {estimate} elements)"
+            )),
+            Plan::Probe(condition, None) => {
+                lines.push(format!("{indent}probe {condition} (no statistics)"))
+            }
+            Plan::Filter(driver, checks) => {
+                let checks: Vec<String> = checks.iter().map(ToString::to_string).collect();
+                lines.push(format!("{indent}check {}", checks.join(" and ")));
+                driver.explain(depth + 1, lines);
+            }
+            Plan::Union(plans) => {
+                lines.push(format!("{indent}union"));
+                for plan in plans {
+                    plan.explain(depth + 1, lines);
+                }
+            }
+        }
+    }
+}
+
+/// Query on the elements of a table or snapshot, created by [`Table::query`](crate::Table::query).
+///
+/// Conditions on indices are planned using the statistics kept by the indices. When all
+/// conditions must hold, the index estimated to match the fewest elements is probed, and the
+/// other conditions are checked on the elements it finds. When any may hold, the elements found
+/// for each of them are merged. Filters are then applied to the fetched elements. When there are
+/// no conditions on indices, all elements are scanned. Results are returned in primary key order.
+pub struct Query<'a, T: Identity> {
+    view: &'a dyn View<T>,
+    conditions: Conditions,
+    filters: Vec<Filter<'a, T>>,
+    limit: Option<usize>,
+}
+
+impl<'a, T: Identity> Query<'a, T> {
+    pub(crate) fn new(view: &'a dyn View<T>) -> Self {
+        Query {
+            view,
+            conditions: Conditions::new(true),
+            filters: Vec::new(),
+            limit: None,
+        }
+    }
+
+    /// Only return elements whose key in the index equals `key`.
+    pub fn eq<K: Any, I: ?Sized>(
+        mut self,
+        index: &IndexHandle<T, K, I>,
+        key: impl Into<K>,
+    ) -> Self {
+        self.conditions = self.conditions.eq(index, key);
+        self
+    }
+
+    /// Only return elements whose key in the index is within the range.
+    pub fn range<K: Any + Clone, I: ?Sized>(
+        mut self,
+        index: &IndexHandle<T, K, I>,
+        range: impl RangeBounds<K>,
+    ) -> Self {
+        self.conditions = self.conditions.range(index, range);
+        self
+    }
+
+    /// Only return elements for which all of the nested conditions hold.
+    pub fn all(mut self, conditions: impl FnOnce(Conditions) -> Conditions) -> Self {
+        self.conditions = self.conditions.all(conditions);
+        self
+    }
+
+    /// Only return elements for which any of the nested conditions holds.
+    pub fn any(mut self, conditions: impl FnOnce(Conditions) -> Conditions) -> Self {
+        self.conditions = self.conditions.any(conditions);
+        self
+    }
+
+    /// Only return elements for which the predicate returns true.
+    pub fn filter(mut self, predicate: impl Fn(&T) -> bool + 'a) -> Self {
+        self.filters.push(Box::new(predicate));
+        self
+    }
+
+    /// Return at most this many elements.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Describe the plan chosen for this query, one step per line.
+    ///
+    /// Every step works on the elements produced by the steps indented below it.
+    pub fn explain(&self) -> Result<String, TableError<T>> {
+        let mut lines = Vec::new();
+        let mut depth = 0;
+        if let Some(limit) = self.limit {
+            lines.push(format!("limit {limit}"));
+            depth += 1;
+        }
+        if !self.filters.is_empty() {
+            let indent = "  ".repeat(depth);
+            lines.push(format!(
+                "{indent}filter by {} predicates",
+                self.filters.len()
+            ));
+            depth += 1;
+        }
+        self.conditions.plan(self.view)?.explain(depth, &mut lines);
+        Ok(lines.join("\n"))
+    }
+
+    /// Run the query, returning the matching elements.
+    pub fn run(self) -> Result<Box<dyn Iterator<Item = &'a T> + 'a>, TableError<T>> {
+        let view = self.view;
+        let rows: Box<dyn Iterator<Item = &'a T> + 'a> =
+            match self.conditions.plan(view)?.keys(view)? {
+                Some(keys) => Box::new(keys.into_iter().filter_map(|key| view.lookup(&key))),
+                None => view.iter(),
+            };
+        let filters = self.filters;
+        let rows = rows.filter(move |row| filters.iter().all(|filter| filter(row)));
+        Ok(match self.limit {
+            Some(limit) => Box::new(rows.take(limit)),
+            None => Box::new(rows),
+        })
+    }
+}
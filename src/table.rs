@@ -1,32 +1,92 @@
 use crate::error::{IndexError, TableError};
-use crate::index::Index;
+use crate::index::{range_is_valid, Index, IndexHandle, TypedIndex};
+use crate::query::Query;
+use foreign::{ForeignKeyCheck, Published};
+pub(crate) use foreign::{Referrer, ReferrerId, Undo};
 use std::any::Any;
-use std::collections::btree_map::Entry;
 use std::collections::*;
 use std::error::Error;
 use std::fmt::Debug;
+use std::ops::{Bound, RangeBounds};
+use std::sync::{Arc, OnceLock};
+use subscription::Subscribers;
+#[cfg(feature = "wal")]
+pub(crate) use transaction::Changes;
+use transaction::Journal;
 
-pub trait Identity {
+mod foreign;
+mod iter;
+#[cfg(feature = "serde")]
+mod persist;
+mod snapshot;
+mod subscription;
+mod threading;
+mod transaction;
+
+pub use foreign::OnRemove;
+pub use iter::{IterMut, RowMut};
+pub use snapshot::{Snapshot, View};
+pub use subscription::Change;
+pub use threading::{Callback, Local, RemovedCallback, Shared, Threading};
+
+/// Element of a table, identified by its primary key.
+///
+/// Elements have to be `'static`, so that typed index handles can recover the type of an index.
+pub trait Identity: 'static {
     type PrimaryKey: Eq + Ord + Clone + Debug + 'static;
     fn primary_key(&self) -> Self::PrimaryKey;
 }
 
-pub struct Table<T: Identity> {
-    data: BTreeMap<T::PrimaryKey, T>,
-    pre_insert_hooks: BTreeMap<String, Box<dyn Fn(&mut Self, &mut T)>>,
-    post_insert_hooks: BTreeMap<String, Box<dyn Fn(&mut Self, &T::PrimaryKey)>>,
-    constraints: BTreeMap<String, Box<dyn Fn(&T) -> Result<(), Box<dyn Error>>>>,
-    indices: BTreeMap<String, Box<dyn Index<T>>>,
+type PrimaryKey<T> = <T as Identity>::PrimaryKey;
+
+/// Elements of a table, in a persistent map so that snapshots can share them.
+type Data<T> = im::OrdMap<PrimaryKey<T>, Arc<T>>;
+
+/// Table of elements, stored by their primary key.
+///
+/// Whether the table can be shared between threads is decided by `S`, see [`Threading`].
+pub struct Table<T: Identity, S: Threading = Local> {
+    data: Data<T>,
+    pre_insert_hooks: BTreeMap<String, Box<S::PreInsertHook<T>>>,
+    post_insert_hooks: BTreeMap<String, Box<S::PostInsertHook<T>>>,
+    pre_update_hooks: BTreeMap<String, Box<S::PreUpdateHook<T>>>,
+    post_update_hooks: BTreeMap<String, Box<S::PostUpdateHook<T>>>,
+    pre_remove_hooks: BTreeMap<String, Box<S::PreRemoveHook<T>>>,
+    post_remove_hooks: BTreeMap<String, Box<S::PostRemoveHook<T>>>,
+    pre_clear_hooks: BTreeMap<String, Box<S::ClearHook<T>>>,
+    post_clear_hooks: BTreeMap<String, Box<S::ClearHook<T>>>,
+    constraints: BTreeMap<String, Box<S::Constraint<T>>>,
+    foreign_keys: BTreeMap<String, ForeignKeyCheck<T>>,
+    referrers: BTreeMap<ReferrerId, Referrer<T>>,
+    published: Option<Published<T>>,
+    indices: BTreeMap<String, Box<S::Index<T>>>,
+    journal: Option<Journal<T>>,
+    subscribers: Option<Subscribers<T, S>>,
+    /// Makes copies of elements that are still shared when they are taken out of the table, set
+    /// by [`Table::snapshot`] and by publishing, the only ways of sharing them.
+    clone: OnceLock<fn(&T) -> T>,
 }
 
-impl<T: Identity> Default for Table<T> {
+impl<T: Identity, S: Threading> Default for Table<T, S> {
     fn default() -> Self {
         Table {
             data: Default::default(),
             pre_insert_hooks: Default::default(),
             post_insert_hooks: Default::default(),
+            pre_update_hooks: Default::default(),
+            post_update_hooks: Default::default(),
+            pre_remove_hooks: Default::default(),
+            post_remove_hooks: Default::default(),
+            pre_clear_hooks: Default::default(),
+            post_clear_hooks: Default::default(),
             constraints: Default::default(),
+            foreign_keys: Default::default(),
+            referrers: Default::default(),
+            published: None,
             indices: Default::default(),
+            journal: None,
+            subscribers: None,
+            clone: OnceLock::new(),
         }
     }
 }
@@ -36,60 +96,214 @@ impl<T: Identity> Table<T> {
     pub fn new() -> Self {
         Table::default()
     }
+}
 
+impl<T: Identity, S: Threading> Table<T, S> {
     /// Get count of elements in table
     pub fn len(&self) -> usize {
         self.data.len()
     }
 
+    /// Determine if this table is empty
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
     /// Clear all data in this table.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any element is still referenced through a foreign key that restricts removal,
+    /// use [`Table::try_clear`] for tables referenced by foreign keys.
     pub fn clear(&mut self) {
-        self.data.clear();
-        for (_, index) in &mut self.indices {
-            index.clear();
-        }
+        self.try_clear().unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Clear all data in this table, unless any element is still referenced.
+    ///
+    /// Fails if any element is still referenced through a foreign key that restricts removal,
+    /// see [`ConcurrentTable::foreign_key_add`](crate::ConcurrentTable::foreign_key_add).
+    pub fn try_clear(&mut self) -> Result<(), TableError<T>> {
+        self.referrers_apply(|table| table.data.keys().cloned().collect())?;
+        self.hooks_apply(
+            |table| &mut table.pre_clear_hooks,
+            |hook, table| hook(table),
+        );
+        self.clear_unchecked();
+        self.hooks_apply(
+            |table| &mut table.post_clear_hooks,
+            |hook, table| hook(table),
+        );
+        Ok(())
     }
 
     /// Try inserting an element
     pub fn insert(&mut self, mut element: T) -> Result<T::PrimaryKey, TableError<T>> {
         // apply pre-insert hooks, need to do this first because they might
-        // modify the element.
-        self.pre_insert_hooks_apply(&mut element);
+        // modify or reject the element.
+        self.pre_insert_hooks_apply(&mut element)?;
 
-        // make sure constraints do not complain.
-        self.constraints_check(&element)?;
+        let primary_key = self.insert_unhooked(element)?;
+
+        // apply post-insert hooks
+        self.post_insert_hooks_apply(&primary_key);
+
+        Ok(primary_key)
+    }
+
+    /// Insert an element without applying hooks.
+    fn insert_unhooked(&mut self, element: T) -> Result<T::PrimaryKey, TableError<T>> {
+        // make sure constraints and primary key do not complain.
+        let primary_key = self.insert_check(&element)?;
 
         // insert into indices
         self.indices_insert(&element)?;
 
         // insert into data
+        self.data.insert(primary_key.clone(), Arc::new(element));
+        self.changed_inserted(&primary_key);
+
+        Ok(primary_key)
+    }
+
+    /// Clear all data without applying hooks.
+    ///
+    /// Fails if any element is still referenced, like [`Table::try_clear`].
+    #[cfg(feature = "wal")]
+    pub(crate) fn clear_unhooked(&mut self) -> Result<(), TableError<T>> {
+        self.referrers_apply(|table| table.data.keys().cloned().collect())?;
+        self.clear_unchecked();
+        Ok(())
+    }
+
+    /// Clear all data without applying hooks or foreign keys referencing the elements.
+    fn clear_unchecked(&mut self) {
+        let data = std::mem::take(&mut self.data);
+        for index in self.indices.values_mut() {
+            index.clear();
+        }
+        self.changed_cleared(data);
+    }
+
+    /// Record that the element with this primary key was inserted.
+    fn changed_inserted(&mut self, key: &T::PrimaryKey) {
+        self.journal_inserted(key);
+        self.publish();
+        self.notify(|_, _| Change::Inserted(key.clone()));
+    }
+
+    /// Record that this element was removed.
+    fn changed_removed(&mut self, element: &T) {
+        self.journal_removed(element);
+        self.publish();
+        self.notify(|clone, _| Change::Removed(clone(element)));
+    }
+
+    /// Record that the old element was replaced by the one with this primary key.
+    fn changed_replaced(&mut self, key: &T::PrimaryKey, old: &T) {
+        self.journal_replaced(key, old);
+        self.publish();
+        self.notify(|clone, table| Change::Updated(clone(old), clone(&table.data[key])));
+    }
+
+    /// Record that these elements were cleared.
+    fn changed_cleared(&mut self, data: Data<T>) {
+        self.journal_cleared(data);
+        self.publish();
+        self.notify(|_, _| Change::Cleared);
+    }
+
+    /// Take an element out of the data, copying it if it is still shared.
+    fn unshare(&self, element: Arc<T>) -> T {
+        Arc::try_unwrap(element).unwrap_or_else(|element| {
+            let clone = self.clone.get().expect("shared elements can be cloned");
+            clone(&element)
+        })
+    }
+
+    /// Check that an element can be inserted, before touching any indices.
+    fn insert_check(&self, element: &T) -> Result<T::PrimaryKey, TableError<T>> {
+        self.constraints_check(element)?;
+
         let primary_key = element.primary_key();
-        match self.data.entry(primary_key.clone()) {
-            Entry::Vacant(entry) => entry.insert(element),
-            Entry::Occupied(_) => {
-                let _ = self.indices_remove(&element);
+        if self.data.contains_key(&primary_key) {
+            return Err(TableError::Exists(primary_key));
+        }
+
+        Ok(primary_key)
+    }
+
+    /// Insert multiple elements, either all of them or none.
+    ///
+    /// Pre-insert hooks and constraints are applied to every element in order. The batch is
+    /// staged outside of the table until every element is validated and every index accepts
+    /// it, using [`Index::insert_bulk`], so hooks only ever see elements that are fully
+    /// inserted. If any element fails, none of the batch is inserted, but changes the
+    /// pre-insert hooks made to the table while the batch was staged are not undone. Run the
+    /// insert inside [`Table::transaction`] to undo those as well.
+    pub fn insert_many(
+        &mut self,
+        elements: impl IntoIterator<Item = T>,
+    ) -> Result<Vec<T::PrimaryKey>, TableError<T>> {
+        let mut keys = Vec::new();
+        let mut staged = Vec::new();
+        let mut batch = BTreeSet::new();
+        for mut element in elements {
+            self.pre_insert_hooks_apply(&mut element)?;
+            let primary_key = self.insert_check(&element)?;
+            if !batch.insert(primary_key.clone()) {
                 return Err(TableError::Exists(primary_key));
             }
-        };
+            keys.push(primary_key);
+            staged.push(element);
+        }
 
-        // apply post-insert hooks
-        self.post_insert_hooks_apply(&primary_key);
+        // hooks of later elements may have inserted rows with the same primary key.
+        if let Some(key) = keys.iter().find(|key| self.data.contains_key(key)) {
+            return Err(TableError::Exists(key.clone()));
+        }
 
-        Ok(primary_key)
+        // insert the whole batch into indices
+        let failed = self.indices.iter_mut().find_map(|(name, index)| {
+            index
+                .insert_bulk(Box::new(staged.iter()))
+                .err()
+                .map(|error| (name.clone(), error))
+        });
+
+        if let Some((name, error)) = failed {
+            // roll back the indices the batch was already inserted into.
+            for (_, index) in self.indices.range_mut::<String, _>(..&name) {
+                let _ = index.remove_bulk(Box::new(staged.iter()));
+            }
+            return Err(TableError::index(&name, error));
+        }
+
+        for (key, element) in keys.iter().zip(staged) {
+            self.data.insert(key.clone(), Arc::new(element));
+        }
+        for key in &keys {
+            self.changed_inserted(key);
+        }
+        for key in &keys {
+            self.post_insert_hooks_apply(key);
+        }
+
+        Ok(keys)
     }
 
     /// Insert an element into all indices.
     fn indices_insert(&mut self, element: &T) -> Result<(), TableError<T>> {
         for (name, index) in self.indices.iter_mut() {
-            use IndexError::*;
-            match index.insert(element) {
-                Ok(()) => {}
-                Err(Duplicate(key)) => {
-                    let name = name.clone();
-                    let _ = self.indices_remove(&element);
-                    return Err(TableError::Duplicate(name, key));
+            if let Err(error) = index.insert(element) {
+                let name = name.clone();
+
+                // roll back the indices this element was already inserted into.
+                for (_, index) in self.indices.range_mut::<String, _>(..&name) {
+                    let _ = index.remove(element);
                 }
-                Err(KeyType) => unreachable!(),
+
+                return Err(TableError::index(&name, error));
             }
         }
         Ok(())
@@ -97,93 +311,173 @@ impl<T: Identity> Table<T> {
 
     /// Remove an element from all indices.
     fn indices_remove(&mut self, element: &T) -> Result<(), TableError<T>> {
-        for (name, index) in self.indices.iter_mut() {
+        for index in self.indices.values_mut() {
             use IndexError::*;
             match index.remove(element) {
                 Ok(()) => {}
-                Err(Duplicate(key)) => unreachable!(),
+                Err(Duplicate(_)) => unreachable!(),
                 Err(KeyType) => unreachable!(),
+                Err(Unsupported) => unreachable!(),
+                Err(InvalidKey) => unreachable!(),
             }
         }
         Ok(())
     }
 
-    /// Adds an index to the table
-    pub fn index_add(
+    /// Adds an index to the table, returning a typed handle to it.
+    pub fn index_add<I: TypedIndex<T> + Callback<S::Index<T>>>(
         &mut self,
         name: &str,
-        mut index: impl Index<T> + 'static,
-    ) -> Result<(), TableError<T>> {
+        mut index: I,
+    ) -> Result<IndexHandle<T, I::Key, I>, TableError<T>> {
         index.clear();
 
         // insert all current data into the index.
-        for (_, data) in &self.data {
-            match index.insert(&data) {
-                Ok(()) => {}
-                // TODO
-                Err(e) => unimplemented!(),
-            }
-        }
+        index
+            .insert_bulk(Box::new(self.data.values().map(Arc::as_ref)))
+            .map_err(|error| TableError::index(name, error))?;
 
-        self.indices.insert(name.to_string(), Box::new(index));
-        Ok(())
+        self.indices.insert(name.to_string(), index.boxed());
+        Ok(IndexHandle::new(name))
     }
 
     /// Removes an index from the table, if it exists.
-    pub fn index_remove(&mut self, name: &str) -> Option<Box<dyn Index<T>>> {
+    pub fn index_remove(&mut self, name: &str) -> Option<Box<S::Index<T>>> {
         self.indices.remove(name)
     }
 
-    /// Check constraints against this element
+    /// Check constraints and foreign keys against this element
     pub fn constraints_check(&self, element: &T) -> Result<(), TableError<T>> {
         for (name, constraint) in self.constraints.iter() {
-            if let Err(error) = constraint(&element) {
+            if let Err(error) = constraint(element) {
                 return Err(TableError::Constraint(name.clone(), error));
             }
         }
-        Ok(())
+        self.foreign_keys_check(element)
     }
 
-    /// Apply pre-insert hooks
-    fn pre_insert_hooks_apply(&mut self, element: &mut T) {
-        let mut pre_insert = std::mem::take(&mut self.pre_insert_hooks);
-        for (_, hook) in pre_insert.iter() {
-            hook(self, element);
+    /// Apply all hooks of one kind.
+    ///
+    /// The hooks are taken out of the table while they run, because they get mutable access to
+    /// the table.
+    fn hooks_apply<H>(
+        &mut self,
+        hooks: fn(&mut Self) -> &mut BTreeMap<String, H>,
+        mut apply: impl FnMut(&H, &mut Self),
+    ) {
+        let _ = self.hooks_try_apply(hooks, |hook, table| {
+            apply(hook, table);
+            Ok(())
+        });
+    }
+
+    /// Apply all hooks of one kind, stopping at the first hook that fails.
+    fn hooks_try_apply<H>(
+        &mut self,
+        hooks: fn(&mut Self) -> &mut BTreeMap<String, H>,
+        mut apply: impl FnMut(&H, &mut Self) -> Result<(), Box<dyn Error + Send + Sync>>,
+    ) -> Result<(), TableError<T>> {
+        let mut taken = std::mem::take(hooks(self));
+        let mut result = Ok(());
+        for (name, hook) in taken.iter() {
+            if let Err(error) = apply(hook, self) {
+                result = Err(TableError::Hook(name.clone(), error));
+                break;
+            }
         }
-        self.pre_insert_hooks = std::mem::take(&mut pre_insert);
+        *hooks(self) = std::mem::take(&mut taken);
+        result
+    }
+
+    /// Apply pre-insert hooks
+    fn pre_insert_hooks_apply(&mut self, element: &mut T) -> Result<(), TableError<T>> {
+        self.hooks_try_apply(
+            |table| &mut table.pre_insert_hooks,
+            |hook, table| hook(table, element),
+        )
     }
 
     /// Apply post-insert hooks
     fn post_insert_hooks_apply(&mut self, key: &T::PrimaryKey) {
-        let mut hooks = std::mem::take(&mut self.post_insert_hooks);
-        for (_, hook) in hooks.iter() {
-            hook(self, key);
-        }
-        self.post_insert_hooks = std::mem::take(&mut hooks);
+        self.hooks_apply(
+            |table| &mut table.post_insert_hooks,
+            |hook, table| hook(table, key),
+        );
     }
 
     /// Try looking up an element by it's primary key
     pub fn lookup(&self, key: &T::PrimaryKey) -> Option<&T> {
-        self.data.get(key)
+        self.data.get(key).map(Arc::as_ref)
+    }
+
+    /// Iterate over all elements, in primary key order.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &T> + '_ {
+        self.data.values().map(Arc::as_ref)
+    }
+
+    /// Iterate over all primary keys, in order.
+    pub fn keys(&self) -> impl DoubleEndedIterator<Item = &T::PrimaryKey> + '_ {
+        self.data.keys()
+    }
+
+    /// Iterate over all elements whose primary key is within the range, in primary key order.
+    pub fn range<R: RangeBounds<T::PrimaryKey>>(
+        &self,
+        range: R,
+    ) -> impl DoubleEndedIterator<Item = &T> + '_ {
+        let valid = range_is_valid(range.start_bound(), range.end_bound());
+        valid
+            .then(|| self.data.range(range))
+            .into_iter()
+            .flatten()
+            .map(|(_, element)| element.as_ref())
+    }
+
+    /// Iterate over all elements after the one with this primary key, in primary key order.
+    ///
+    /// Used for pagination: the key of the last element on a page is the cursor to the next
+    /// one, as in `table.after(&last).take(10)`. The element with the key does not need to
+    /// exist anymore.
+    pub fn after(&self, key: &T::PrimaryKey) -> impl DoubleEndedIterator<Item = &T> + '_ {
+        self.range((Bound::Excluded(key.clone()), Bound::Unbounded))
     }
 
-    /// Lookup in index
+    /// Lookup in index, resolving the matching primary keys into rows.
     pub fn index_lookup(
         &self,
         index: &str,
         key: &dyn Any,
-    ) -> Result<Box<dyn Iterator<Item = &T>>, TableError<T>> {
-        unimplemented!()
+    ) -> Result<Box<dyn Iterator<Item = &T> + '_>, TableError<T>> {
+        View::index_lookup(self, index, key)
+    }
+
+    /// Lookup all rows whose key in the index is within the bounds, in key order.
+    ///
+    /// The returned iterator can be reversed to visit the rows in descending key order.
+    pub fn index_range(
+        &self,
+        index: &str,
+        start: Bound<&dyn Any>,
+        end: Bound<&dyn Any>,
+    ) -> Result<Box<dyn DoubleEndedIterator<Item = &T> + '_>, TableError<T>> {
+        View::index_range(self, index, start, end)
+    }
+
+    /// Start a query on the elements of this table, see [`Query`].
+    pub fn query(&self) -> Query<'_, T> {
+        Query::new(self)
     }
 
     /// Add a constraint to this table
     pub fn constraint_add(
         &mut self,
         name: &str,
-        constraint: impl Fn(&T) -> Result<(), Box<dyn Error>> + 'static,
+        constraint: impl Fn(&T) -> Result<(), Box<dyn Error + Send + Sync>>
+            + Callback<S::Constraint<T>>
+            + 'static,
     ) -> Result<(), TableError<T>> {
         // make sure this constraint works with existing data
-        for (_, value) in &self.data {
+        for value in self.data.values() {
             if let Err(error) = constraint(value) {
                 return Err(TableError::Constraint(name.to_string(), error));
             }
@@ -191,7 +485,7 @@ impl<T: Identity> Table<T> {
 
         // add constraint
         self.constraints
-            .insert(name.to_string(), Box::new(constraint));
+            .insert(name.to_string(), constraint.boxed());
 
         Ok(())
     }
@@ -202,8 +496,349 @@ impl<T: Identity> Table<T> {
     }
 
     /// Add a pre-insert hook to the table
-    pub fn pre_insert_hook_add(&mut self, name: &str, hook: impl Fn(&mut Self, &mut T) + 'static) {
+    pub fn pre_insert_hook_add(
+        &mut self,
+        name: &str,
+        hook: impl Fn(&mut Self, &mut T) + Callback<S::PreInsertHookInfallible<T>> + 'static,
+    ) where
+        T: 'static,
+    {
         self.pre_insert_hooks
-            .insert(name.to_string(), Box::new(hook));
+            .insert(name.to_string(), S::pre_insert_hook_fallible(hook.boxed()));
+    }
+
+    /// Add a fallible pre-insert hook to the table
+    ///
+    /// If the hook fails, the insert is aborted with [`TableError::Hook`] before any index is
+    /// touched.
+    pub fn pre_insert_hook_try_add(
+        &mut self,
+        name: &str,
+        hook: impl Fn(&mut Self, &mut T) -> Result<(), Box<dyn Error + Send + Sync>>
+            + Callback<S::PreInsertHook<T>>
+            + 'static,
+    ) {
+        self.pre_insert_hooks.insert(name.to_string(), hook.boxed());
+    }
+
+    /// Remove a pre-insert hook from this table
+    pub fn pre_insert_hook_remove(&mut self, name: &str) {
+        self.pre_insert_hooks.remove(name);
+    }
+
+    /// Add a post-insert hook to the table, which is called with the key of the new element
+    pub fn post_insert_hook_add(
+        &mut self,
+        name: &str,
+        hook: impl Fn(&mut Self, &T::PrimaryKey) + Callback<S::PostInsertHook<T>> + 'static,
+    ) {
+        self.post_insert_hooks
+            .insert(name.to_string(), hook.boxed());
+    }
+
+    /// Remove a post-insert hook from this table
+    pub fn post_insert_hook_remove(&mut self, name: &str) {
+        self.post_insert_hooks.remove(name);
+    }
+
+    /// Add a pre-update hook to the table, which is called with the key of the element and the
+    /// new version, which it may modify
+    pub fn pre_update_hook_add(
+        &mut self,
+        name: &str,
+        hook: impl Fn(&mut Self, &T::PrimaryKey, &mut T) + Callback<S::PreUpdateHook<T>> + 'static,
+    ) {
+        self.pre_update_hooks.insert(name.to_string(), hook.boxed());
+    }
+
+    /// Remove a pre-update hook from this table
+    pub fn pre_update_hook_remove(&mut self, name: &str) {
+        self.pre_update_hooks.remove(name);
+    }
+
+    /// Add a post-update hook to the table, which is called with the key of the new version and
+    /// the old version of the element
+    pub fn post_update_hook_add(
+        &mut self,
+        name: &str,
+        hook: impl Fn(&mut Self, &T::PrimaryKey, &T) + Callback<S::PostUpdateHook<T>> + 'static,
+    ) {
+        self.post_update_hooks
+            .insert(name.to_string(), hook.boxed());
+    }
+
+    /// Remove a post-update hook from this table
+    pub fn post_update_hook_remove(&mut self, name: &str) {
+        self.post_update_hooks.remove(name);
+    }
+
+    /// Add a pre-remove hook to the table, which is called with the key of the element
+    pub fn pre_remove_hook_add(
+        &mut self,
+        name: &str,
+        hook: impl Fn(&mut Self, &T::PrimaryKey) + Callback<S::PreRemoveHook<T>> + 'static,
+    ) {
+        self.pre_remove_hooks.insert(name.to_string(), hook.boxed());
+    }
+
+    /// Remove a pre-remove hook from this table
+    pub fn pre_remove_hook_remove(&mut self, name: &str) {
+        self.pre_remove_hooks.remove(name);
+    }
+
+    /// Add a post-remove hook to the table, which is called with the removed element
+    pub fn post_remove_hook_add(
+        &mut self,
+        name: &str,
+        hook: impl Fn(&mut Self, &T) + RemovedCallback<S::PostRemoveHook<T>> + 'static,
+    ) {
+        self.post_remove_hooks
+            .insert(name.to_string(), hook.boxed());
+    }
+
+    /// Remove a post-remove hook from this table
+    pub fn post_remove_hook_remove(&mut self, name: &str) {
+        self.post_remove_hooks.remove(name);
+    }
+
+    /// Add a pre-clear hook to the table
+    pub fn pre_clear_hook_add(
+        &mut self,
+        name: &str,
+        hook: impl Fn(&mut Self) + Callback<S::ClearHook<T>> + 'static,
+    ) {
+        self.pre_clear_hooks.insert(name.to_string(), hook.boxed());
+    }
+
+    /// Remove a pre-clear hook from this table
+    pub fn pre_clear_hook_remove(&mut self, name: &str) {
+        self.pre_clear_hooks.remove(name);
+    }
+
+    /// Add a post-clear hook to the table
+    pub fn post_clear_hook_add(
+        &mut self,
+        name: &str,
+        hook: impl Fn(&mut Self) + Callback<S::ClearHook<T>> + 'static,
+    ) {
+        self.post_clear_hooks.insert(name.to_string(), hook.boxed());
+    }
+
+    /// Remove a post-clear hook from this table
+    pub fn post_clear_hook_remove(&mut self, name: &str) {
+        self.post_clear_hooks.remove(name);
+    }
+
+    /// Insert an element, or replace the existing element with the same primary key, without
+    /// applying hooks.
+    #[cfg(feature = "wal")]
+    pub(crate) fn upsert_unhooked(&mut self, element: T) -> Result<T::PrimaryKey, TableError<T>> {
+        let primary_key = element.primary_key();
+        if self.data.contains_key(&primary_key) {
+            self.replace(&primary_key, element)?;
+            Ok(primary_key)
+        } else {
+            self.insert_unhooked(element)
+        }
+    }
+
+    /// Remove an element without applying hooks.
+    ///
+    /// Fails if the element is still referenced, like [`Table::try_remove`].
+    #[cfg(feature = "wal")]
+    pub(crate) fn remove_unhooked(
+        &mut self,
+        key: &T::PrimaryKey,
+    ) -> Result<Option<T>, TableError<T>> {
+        if !self.data.contains_key(key) {
+            return Ok(None);
+        }
+        self.referrers_apply(|_| BTreeSet::from([key.clone()]))?;
+        Ok(self.remove_unchecked(key))
+    }
+
+    /// Remove an element without applying hooks or foreign keys referencing it.
+    fn remove_unchecked(&mut self, key: &T::PrimaryKey) -> Option<T> {
+        let element = self.data.remove(key)?;
+        let element = self.unshare(element);
+        let _ = self.indices_remove(&element);
+        self.changed_removed(&element);
+        Some(element)
+    }
+
+    /// Insert an element, or replace the existing element with the same primary key.
+    ///
+    /// When no element with this primary key exists, this behaves like [`Table::insert`].
+    /// Otherwise the existing element is replaced and re-indexed, running the update hooks
+    /// instead of the insert hooks. If the new version is rejected, the old version is kept
+    /// intact.
+    pub fn upsert(&mut self, element: T) -> Result<T::PrimaryKey, TableError<T>> {
+        let primary_key = element.primary_key();
+        if self.data.contains_key(&primary_key) {
+            self.update_apply(&primary_key, element)
+        } else {
+            self.insert(element)
+        }
+    }
+
+    /// Replace the element stored under `key`, applying the update hooks.
+    fn update_apply(
+        &mut self,
+        key: &T::PrimaryKey,
+        mut element: T,
+    ) -> Result<T::PrimaryKey, TableError<T>> {
+        self.hooks_apply(
+            |table| &mut table.pre_update_hooks,
+            |hook, table| hook(table, key, &mut element),
+        );
+        let primary_key = element.primary_key();
+        let old = self.replace(key, element)?;
+        self.hooks_apply(
+            |table| &mut table.post_update_hooks,
+            |hook, table| hook(table, &primary_key, &old),
+        );
+        Ok(primary_key)
+    }
+
+    /// Replace the element stored under `key` with `element`, returning the old version.
+    ///
+    /// Checks constraints and re-indexes the element. On failure, the old version is restored.
+    fn replace(&mut self, key: &T::PrimaryKey, element: T) -> Result<T, TableError<T>> {
+        self.constraints_check(&element)?;
+
+        // the primary key may have changed, it must not collide with another element.
+        let primary_key = element.primary_key();
+        if &primary_key != key && self.data.contains_key(&primary_key) {
+            return Err(TableError::Exists(primary_key));
+        }
+
+        let old = match self.data.remove(key) {
+            Some(old) => old,
+            None => return Err(TableError::NotFound(key.clone())),
+        };
+        let _ = self.indices_remove(&old);
+        let mut result = self.indices_insert(&element);
+
+        // an element whose primary key changed is gone under its old key.
+        if result.is_ok() && &primary_key != key {
+            result = self.referrers_apply(|_| BTreeSet::from([key.clone()]));
+            if result.is_err() {
+                let _ = self.indices_remove(&element);
+            }
+        }
+        if let Err(error) = result {
+            // restore the old version, it was indexed before so this cannot fail.
+            let _ = self.indices_insert(&old);
+            self.data.insert(key.clone(), old);
+            return Err(error);
+        }
+
+        self.data.insert(primary_key.clone(), Arc::new(element));
+        let old = self.unshare(old);
+        self.changed_replaced(&primary_key, &old);
+        Ok(old)
+    }
+
+    /// Remove an element by its primary key, returning it if it existed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the element is still referenced through a foreign key that restricts removal,
+    /// use [`Table::try_remove`] for tables referenced by foreign keys.
+    pub fn remove(&mut self, key: &T::PrimaryKey) -> Option<T> {
+        self.try_remove(key)
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Remove an element by its primary key, returning it if it existed, unless it is still
+    /// referenced.
+    ///
+    /// Fails if the element is still referenced through a foreign key that restricts removal,
+    /// see [`ConcurrentTable::foreign_key_add`](crate::ConcurrentTable::foreign_key_add).
+    pub fn try_remove(&mut self, key: &T::PrimaryKey) -> Result<Option<T>, TableError<T>> {
+        if !self.data.contains_key(key) {
+            return Ok(None);
+        }
+        self.referrers_apply(|_| BTreeSet::from([key.clone()]))?;
+        Ok(self.remove_referenced(key))
+    }
+
+    /// Remove an element whose referrers were already applied, applying the remove hooks.
+    fn remove_referenced(&mut self, key: &T::PrimaryKey) -> Option<T> {
+        self.hooks_apply(
+            |table| &mut table.pre_remove_hooks,
+            |hook, table| hook(table, key),
+        );
+
+        // hooks may have removed the element already.
+        let element = self.remove_unchecked(key)?;
+        self.hooks_apply(
+            |table| &mut table.post_remove_hooks,
+            |hook, table| hook(table, &element),
+        );
+        Some(element)
+    }
+
+    /// Remove all elements for which the predicate returns false.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of these elements is still referenced through a foreign key that restricts
+    /// removal, use [`Table::try_retain`] for tables referenced by foreign keys.
+    pub fn retain(&mut self, predicate: impl FnMut(&T) -> bool) {
+        self.try_retain(predicate)
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Remove all elements for which the predicate returns false, unless any of them is still
+    /// referenced.
+    ///
+    /// Foreign keys referencing the elements are checked for all of them at once, so either all
+    /// of them are removed, or none are if any of them is still referenced, see
+    /// [`Table::try_remove`].
+    pub fn try_retain(
+        &mut self,
+        mut predicate: impl FnMut(&T) -> bool,
+    ) -> Result<(), TableError<T>> {
+        let keys: BTreeSet<T::PrimaryKey> = self
+            .data
+            .iter()
+            .filter(|(_, element)| !predicate(element))
+            .map(|(key, _)| key.clone())
+            .collect();
+        self.try_remove_keys(&keys)
+    }
+
+    /// Remove all elements with these primary keys, unless any of them is still referenced.
+    pub(crate) fn try_remove_keys(
+        &mut self,
+        keys: &BTreeSet<T::PrimaryKey>,
+    ) -> Result<(), TableError<T>> {
+        self.referrers_apply(|_| keys.clone())?;
+        for key in keys {
+            self.remove_referenced(key);
+        }
+        Ok(())
+    }
+}
+
+impl<T: Identity + Clone, S: Threading> Table<T, S> {
+    /// Update an element in place, re-checking constraints and re-indexing it.
+    ///
+    /// The closure works on a copy of the element. If the new version violates a constraint or
+    /// an index, the old version is kept intact. Returns the primary key of the new version,
+    /// which the closure may have changed. Changing it removes the element under its old key
+    /// as far as foreign keys referencing this table are concerned.
+    pub fn update(
+        &mut self,
+        key: &T::PrimaryKey,
+        update: impl FnOnce(&mut T),
+    ) -> Result<T::PrimaryKey, TableError<T>> {
+        let mut element = match self.data.get(key) {
+            Some(element) => T::clone(element),
+            None => return Err(TableError::NotFound(key.clone())),
+        };
+        update(&mut element);
+        self.update_apply(key, element)
     }
 }
@@ -0,0 +1,175 @@
+use super::{Data, PrimaryKey, Table, Threading};
+use crate::{Identity, TableError};
+use std::collections::BTreeSet;
+use std::sync::{Arc, PoisonError, RwLock};
+
+/// Checks that the element referenced by an element exists.
+pub(crate) type ForeignKeyCheck<T> = Box<dyn Fn(&T) -> bool + Send + Sync>;
+
+/// Primary keys being removed.
+pub(crate) type Removed<'a, T> = &'a BTreeSet<PrimaryKey<T>>;
+
+/// Committed elements of a table, which elements of other tables can reference.
+///
+/// Kept apart from the table, so checking a reference never waits for the referenced table.
+pub(crate) type Published<T> = Arc<RwLock<Data<T>>>;
+
+/// Undoes the changes a foreign key made to the elements of the table referencing this one.
+pub(crate) type Undo = Box<dyn FnOnce() + Send + Sync>;
+
+/// Action on the elements of another table that reference elements of this one.
+///
+/// Called with the primary keys being removed and whether to refuse removing any referenced
+/// element instead of acting, returns the first one it refuses to let go of. If it changed the
+/// other table, it returns how to undo that.
+pub(crate) type ReferrerAction<T> =
+    Box<dyn Fn(Removed<'_, T>, bool) -> Result<Option<Undo>, PrimaryKey<T>> + Send + Sync>;
+
+/// Identifies a foreign key referencing a table, by the address of the referencing table and the
+/// name of the foreign key, so tables can use the same name for foreign keys to the same table.
+pub(crate) type ReferrerId = (usize, String);
+
+/// Foreign key of another table referencing elements of this one.
+pub(crate) struct Referrer<T: Identity> {
+    /// Whether the foreign key restricts removal, these are checked before any others act.
+    pub(crate) restrict: bool,
+    pub(crate) action: ReferrerAction<T>,
+}
+
+/// What happens to referencing elements when the element they reference is removed.
+#[derive(Debug)]
+pub enum OnRemove<T> {
+    /// Refuse to remove referenced elements.
+    Restrict,
+    /// Remove the referencing elements as well.
+    Cascade,
+    /// Update the referencing elements with this function, which must clear the reference.
+    SetNull(fn(&mut T)),
+}
+
+impl<T> Clone for OnRemove<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for OnRemove<T> {}
+
+impl<T: Identity, S: Threading> Table<T, S> {
+    /// Add a check that the elements referenced through a foreign key exist.
+    pub(crate) fn foreign_key_check_add(&mut self, name: &str, check: ForeignKeyCheck<T>) {
+        self.foreign_keys.insert(name.to_string(), check);
+    }
+
+    /// Remove the check of a foreign key.
+    pub(crate) fn foreign_key_check_remove(&mut self, name: &str) {
+        self.foreign_keys.remove(name);
+    }
+
+    /// Check that the elements referenced by this element exist.
+    pub(super) fn foreign_keys_check(&self, element: &T) -> Result<(), TableError<T>> {
+        for (name, check) in self.foreign_keys.iter() {
+            if !check(element) {
+                return Err(TableError::ForeignKey(name.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Determine if this table has a foreign key with this name.
+    pub(crate) fn foreign_key_exists(&self, name: &str) -> bool {
+        self.foreign_keys.contains_key(name)
+    }
+
+    /// Add a foreign key of another table referencing elements of this one.
+    pub(crate) fn referrer_add(&mut self, id: ReferrerId, referrer: Referrer<T>) {
+        self.referrers.insert(id, referrer);
+    }
+
+    /// Remove a foreign key of another table referencing elements of this one.
+    pub(crate) fn referrer_remove(&mut self, id: &ReferrerId) {
+        self.referrers.remove(id);
+        if self.referrers.is_empty() {
+            self.published = None;
+        }
+    }
+
+    /// Publish the elements of this table, unless a transaction has not committed them yet.
+    pub(super) fn publish(&self) {
+        if let (Some(published), None) = (&self.published, &self.journal) {
+            *published.write().unwrap_or_else(PoisonError::into_inner) = self.data.clone();
+        }
+    }
+
+    /// Apply the foreign keys referencing the elements being removed.
+    ///
+    /// Restricting foreign keys are checked first. If any foreign key refuses, the changes the
+    /// others made are undone, so nothing is changed. Inside a transaction, the changes are
+    /// recorded so they are undone when it is rolled back. Only transactions started by other
+    /// foreign keys are rolled back as part of the same removal though, so inside any other
+    /// transaction all foreign keys restrict removal.
+    pub(super) fn referrers_apply(
+        &mut self,
+        removed: impl FnOnce(&Self) -> BTreeSet<PrimaryKey<T>>,
+    ) -> Result<(), TableError<T>> {
+        if self.referrers.is_empty() {
+            return Ok(());
+        }
+        let removed = removed(self);
+
+        // hide the elements first, so no element referencing them can be added meanwhile.
+        let mut hidden = Vec::new();
+        if let Some(published) = &self.published {
+            let mut published = published.write().unwrap_or_else(PoisonError::into_inner);
+            hidden.extend(
+                removed
+                    .iter()
+                    .filter_map(|key| Some((key.clone(), published.remove(key)?))),
+            );
+        }
+
+        let restrict = self
+            .journal
+            .as_ref()
+            .is_some_and(|journal| !journal.undoable);
+        let restricting = self
+            .referrers
+            .iter()
+            .filter(|(_, referrer)| referrer.restrict);
+        let acting = self
+            .referrers
+            .iter()
+            .filter(|(_, referrer)| !referrer.restrict);
+        let mut undos = Vec::new();
+        for ((_, name), referrer) in restricting.chain(acting) {
+            match (referrer.action)(&removed, referrer.restrict || restrict) {
+                Ok(undo) => undos.extend(undo),
+                Err(key) => {
+                    for undo in undos.into_iter().rev() {
+                        undo();
+                    }
+                    if let Some(published) = &self.published {
+                        let mut published =
+                            published.write().unwrap_or_else(PoisonError::into_inner);
+                        published.extend(hidden);
+                    }
+                    return Err(TableError::Referenced(name.clone(), key));
+                }
+            }
+        }
+        self.journal_referrers(undos);
+        Ok(())
+    }
+}
+
+impl<T: Identity + Clone, S: Threading> Table<T, S> {
+    /// Get the committed elements of this table, publishing them from now on.
+    pub(crate) fn published(&mut self) -> Published<T> {
+        // elements shared with the published ones are copied when they are taken out of the table.
+        self.clone.get_or_init(|| T::clone);
+        let data = &self.data;
+        self.published
+            .get_or_insert_with(|| Arc::new(RwLock::new(data.clone())))
+            .clone()
+    }
+}
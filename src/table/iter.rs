@@ -0,0 +1,121 @@
+use super::{Local, PrimaryKey, Table, Threading};
+use crate::{Identity, TableError};
+use std::ops::{Deref, DerefMut};
+
+/// Primary keys of rows whose changes could not be written back, along with the reason.
+type Failed<T> = Vec<(PrimaryKey<T>, TableError<T>)>;
+
+/// Mutable iteration over the elements of a table, created by [`Table::iter_mut`].
+///
+/// Every row borrows the table until it is dropped, so this is not an [`Iterator`]; use it with
+/// `while let Some(mut row) = rows.next()`. Rows are visited in primary key order, starting
+/// from the keys the table had when iteration began. Changes of rows that fail to be written
+/// back when dropped are collected, call [`IterMut::finish`] afterwards to get them.
+pub struct IterMut<'a, T: Identity + Clone, S: Threading = Local> {
+    table: &'a mut Table<T, S>,
+    keys: std::vec::IntoIter<T::PrimaryKey>,
+    errors: Failed<T>,
+}
+
+impl<'a, T: Identity + Clone, S: Threading> IterMut<'a, T, S> {
+    /// Get the next row, skipping elements that were removed in the meantime.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<RowMut<'_, T, S>> {
+        for key in self.keys.by_ref() {
+            if let Some(element) = self.table.data.get(&key) {
+                let element = T::clone(element);
+                return Some(RowMut {
+                    table: self.table,
+                    errors: &mut self.errors,
+                    key,
+                    element: Some(element),
+                    changed: false,
+                });
+            }
+        }
+        None
+    }
+
+    /// Finish iterating, returning the primary keys of the rows whose changes could not be
+    /// written back when they were dropped, along with the reason.
+    ///
+    /// Rows that were committed with [`RowMut::commit`] report their errors there instead.
+    pub fn finish(self) -> Result<(), Failed<T>> {
+        match self.errors.is_empty() {
+            true => Ok(()),
+            false => Err(self.errors),
+        }
+    }
+}
+
+/// Element of a table that can be changed in place, see [`Table::iter_mut`].
+///
+/// Changes are made to a copy of the element, which is written back when the row is dropped,
+/// the same way as [`Table::update`] does. If the new version violates a constraint or an
+/// index, the old version is kept; use [`RowMut::commit`] to find out right away, or
+/// [`IterMut::finish`] to find out for all dropped rows. Rows that were not borrowed mutably are
+/// left alone.
+pub struct RowMut<'a, T: Identity + Clone, S: Threading = Local> {
+    table: &'a mut Table<T, S>,
+    /// Errors of the iteration this row belongs to, for when writing back on drop fails.
+    errors: &'a mut Failed<T>,
+    key: T::PrimaryKey,
+    element: Option<T>,
+    changed: bool,
+}
+
+impl<'a, T: Identity + Clone, S: Threading> RowMut<'a, T, S> {
+    /// Primary key of the element, as it was before any changes.
+    pub fn key(&self) -> &T::PrimaryKey {
+        &self.key
+    }
+
+    /// Write the changes back, returning the primary key of the new version.
+    pub fn commit(mut self) -> Result<T::PrimaryKey, TableError<T>> {
+        self.write()
+    }
+
+    fn write(&mut self) -> Result<T::PrimaryKey, TableError<T>> {
+        match self.element.take() {
+            Some(element) if self.changed => self.table.update_apply(&self.key, element),
+            _ => Ok(self.key.clone()),
+        }
+    }
+}
+
+impl<'a, T: Identity + Clone, S: Threading> Deref for RowMut<'a, T, S> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.element.as_ref().unwrap()
+    }
+}
+
+impl<'a, T: Identity + Clone, S: Threading> DerefMut for RowMut<'a, T, S> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.changed = true;
+        self.element.as_mut().unwrap()
+    }
+}
+
+impl<'a, T: Identity + Clone, S: Threading> Drop for RowMut<'a, T, S> {
+    fn drop(&mut self) {
+        if let Err(error) = self.write() {
+            self.errors.push((self.key.clone(), error));
+        }
+    }
+}
+
+impl<T: Identity + Clone, S: Threading> Table<T, S> {
+    /// Iterate over all elements mutably, in primary key order.
+    ///
+    /// Changed elements are re-checked and re-indexed as each row is dropped, see [`RowMut`].
+    pub fn iter_mut(&mut self) -> IterMut<'_, T, S> {
+        let keys: Vec<T::PrimaryKey> = self.data.keys().cloned().collect();
+        IterMut {
+            table: self,
+            keys: keys.into_iter(),
+            errors: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use super::{Data, Table, Threading};
+use crate::{Identity, Index, TableError};
+use serde::de::{Deserialize, Deserializer, Error};
+use serde::ser::{Serialize, Serializer};
+use std::ops::RangeBounds;
+use std::sync::Arc;
+
+/// Tables serialize as a sequence of their elements, in primary key order.
+///
+/// Indices, constraints and hooks are code and are not serialized. After deserializing a table,
+/// they are re-applied with [`Table::index_add`] and [`Table::constraint_add`], which check the
+/// restored elements. To restore elements into a table that already has them registered, use
+/// [`Table::restore`].
+impl<T: Identity + Serialize, S: Threading> Serialize for Table<T, S> {
+    fn serialize<Z: Serializer>(&self, serializer: Z) -> Result<Z::Ok, Z::Error> {
+        serializer.collect_seq(self.data.values().map(Arc::as_ref))
+    }
+}
+
+impl<'de, T: Identity + Deserialize<'de>, S: Threading> Deserialize<'de> for Table<T, S> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut table = Table::default();
+        table.restore(deserializer)?;
+        Ok(table)
+    }
+}
+
+impl<T: Identity, S: Threading> Table<T, S> {
+    /// Replace the elements of this table with ones deserialized from a snapshot.
+    ///
+    /// The restored elements are checked against the constraints and indices registered on this
+    /// table, but pre-insert hooks are not applied, because the elements already went through
+    /// them when they were first inserted. Elements missing from the snapshot are removed
+    /// through the foreign keys referencing this table, like [`Table::try_clear`]. If any element
+    /// is rejected or any removal is restricted, the table is left unchanged.
+    pub fn restore<'de, D>(&mut self, deserializer: D) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+        T: Deserialize<'de>,
+    {
+        let elements = Vec::<T>::deserialize(deserializer)?;
+        self.load(elements).map_err(D::Error::custom)
+    }
+
+    /// Replace the elements of this table, without applying hooks.
+    fn load(&mut self, elements: Vec<T>) -> Result<(), TableError<T>> {
+        let mut data = Data::new();
+        for element in elements {
+            self.constraints_check(&element)?;
+            let primary_key = element.primary_key();
+            if data.contains_key(&primary_key) {
+                return Err(TableError::Exists(primary_key));
+            }
+            data.insert(primary_key, Arc::new(element));
+        }
+
+        // rebuild indices from the new data, and back from the old data if that fails.
+        let failed = self.indices.iter_mut().find_map(|(name, index)| {
+            index.clear();
+            index
+                .insert_bulk(Box::new(data.values().map(Arc::as_ref)))
+                .err()
+                .map(|error| (name.clone(), error))
+        });
+        if let Some((name, error)) = failed {
+            self.indices_rebuild(..=&name);
+            return Err(TableError::index(&name, error));
+        }
+
+        // elements missing from the new data are removed, foreign keys referencing them decide.
+        if let Err(error) = self.referrers_apply(|table| {
+            table
+                .data
+                .keys()
+                .filter(|key| !data.contains_key(key))
+                .cloned()
+                .collect()
+        }) {
+            self.indices_rebuild(..);
+            return Err(error);
+        }
+
+        let keys: Vec<T::PrimaryKey> = data.keys().cloned().collect();
+        let old = std::mem::replace(&mut self.data, data);
+        self.changed_cleared(old);
+        for key in &keys {
+            self.changed_inserted(key);
+        }
+
+        Ok(())
+    }
+
+    /// Rebuild the indices with names in the range from the current data.
+    fn indices_rebuild<R: RangeBounds<String>>(&mut self, names: R) {
+        for (_, index) in self.indices.range_mut(names) {
+            index.clear();
+            let _ = index.insert_bulk(Box::new(self.data.values().map(Arc::as_ref)));
+        }
+    }
+}
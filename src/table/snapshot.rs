@@ -0,0 +1,176 @@
+use super::{Data, Local, Table, Threading};
+use crate::query::Query;
+use crate::{Identity, Index, TableError};
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::ops::{Bound, Deref};
+use std::sync::Arc;
+
+/// Read access to the elements and indices of a table, or of a snapshot of one.
+///
+/// Index handles work with anything implementing this, including locked and durable tables.
+pub trait View<T: Identity> {
+    /// Try looking up an element by its primary key.
+    fn lookup(&self, key: &T::PrimaryKey) -> Option<&T>;
+
+    /// Iterate over all elements, in primary key order.
+    fn iter(&self) -> Box<dyn DoubleEndedIterator<Item = &T> + '_>;
+
+    /// Get the index with this name.
+    fn index(&self, name: &str) -> Result<&dyn Index<T>, TableError<T>>;
+
+    /// Lookup in index, resolving the matching primary keys into rows.
+    fn index_lookup(
+        &self,
+        index: &str,
+        key: &dyn Any,
+    ) -> Result<Box<dyn Iterator<Item = &T> + '_>, TableError<T>> {
+        let keys = self
+            .index(index)?
+            .lookup(key)
+            .map_err(|error| TableError::index(index, error))?;
+        Ok(Box::new(keys.filter_map(|key| self.lookup(&key))))
+    }
+
+    /// Lookup all rows whose key in the index is within the bounds, in key order.
+    fn index_range(
+        &self,
+        index: &str,
+        start: Bound<&dyn Any>,
+        end: Bound<&dyn Any>,
+    ) -> Result<Box<dyn DoubleEndedIterator<Item = &T> + '_>, TableError<T>> {
+        let keys = self
+            .index(index)?
+            .range(start, end)
+            .map_err(|error| TableError::index(index, error))?;
+        Ok(Box::new(keys.filter_map(|key| self.lookup(&key))))
+    }
+}
+
+impl<T: Identity, S: Threading> View<T> for Table<T, S> {
+    fn lookup(&self, key: &T::PrimaryKey) -> Option<&T> {
+        Table::lookup(self, key)
+    }
+
+    fn iter(&self) -> Box<dyn DoubleEndedIterator<Item = &T> + '_> {
+        Box::new(Table::iter(self))
+    }
+
+    fn index(&self, name: &str) -> Result<&dyn Index<T>, TableError<T>> {
+        self.indices
+            .get(name)
+            .map(|index| S::index_ref(index))
+            .ok_or_else(|| TableError::UnknownIndex(name.to_string()))
+    }
+}
+
+impl<T: Identity, V: Deref> View<T> for V
+where
+    V::Target: View<T>,
+{
+    fn lookup(&self, key: &T::PrimaryKey) -> Option<&T> {
+        self.deref().lookup(key)
+    }
+
+    fn iter(&self) -> Box<dyn DoubleEndedIterator<Item = &T> + '_> {
+        self.deref().iter()
+    }
+
+    fn index(&self, name: &str) -> Result<&dyn Index<T>, TableError<T>> {
+        self.deref().index(name)
+    }
+}
+
+/// Point-in-time, read-only view of a table.
+///
+/// Taking a snapshot is cheap, because the elements and indices are stored in persistent data
+/// structures which the snapshot shares with the table. Changes made to the table afterwards
+/// copy only the parts they touch, so the snapshot keeps seeing the old state without holding
+/// on to the table.
+///
+/// Indices that cannot take snapshots, such as R-tree indices, fail with
+/// [`TableError::Unsupported`] when queried through a snapshot. Snapshots of a
+/// [`Shared`](super::Shared) table can be sent to other threads.
+pub struct Snapshot<T: Identity, S: Threading = Local> {
+    data: Data<T>,
+    indices: BTreeMap<String, Option<Box<S::Index<T>>>>,
+}
+
+impl<T: Identity, S: Threading> Snapshot<T, S> {
+    /// Get count of elements in snapshot
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Determine if this snapshot is empty
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Try looking up an element by it's primary key
+    pub fn lookup(&self, key: &T::PrimaryKey) -> Option<&T> {
+        self.data.get(key).map(Arc::as_ref)
+    }
+
+    /// Lookup in index, see [`Table::index_lookup`].
+    pub fn index_lookup(
+        &self,
+        index: &str,
+        key: &dyn Any,
+    ) -> Result<Box<dyn Iterator<Item = &T> + '_>, TableError<T>> {
+        View::index_lookup(self, index, key)
+    }
+
+    /// Lookup a range in index, see [`Table::index_range`].
+    pub fn index_range(
+        &self,
+        index: &str,
+        start: Bound<&dyn Any>,
+        end: Bound<&dyn Any>,
+    ) -> Result<Box<dyn DoubleEndedIterator<Item = &T> + '_>, TableError<T>> {
+        View::index_range(self, index, start, end)
+    }
+
+    /// Start a query on the elements of this snapshot, see [`Query`].
+    pub fn query(&self) -> Query<'_, T> {
+        Query::new(self)
+    }
+}
+
+impl<T: Identity, S: Threading> View<T> for Snapshot<T, S> {
+    fn lookup(&self, key: &T::PrimaryKey) -> Option<&T> {
+        Snapshot::lookup(self, key)
+    }
+
+    fn iter(&self) -> Box<dyn DoubleEndedIterator<Item = &T> + '_> {
+        Box::new(self.data.values().map(Arc::as_ref))
+    }
+
+    fn index(&self, name: &str) -> Result<&dyn Index<T>, TableError<T>> {
+        match self.indices.get(name) {
+            Some(Some(index)) => Ok(S::index_ref(index)),
+            Some(None) => Err(TableError::Unsupported(name.to_string())),
+            None => Err(TableError::UnknownIndex(name.to_string())),
+        }
+    }
+}
+
+impl<T: Identity + Clone, S: Threading> Table<T, S> {
+    /// Take a snapshot of the current state of this table.
+    ///
+    /// The snapshot does not borrow the table, so it can be read for as long as needed while
+    /// the table keeps changing, for example from another thread if the table is
+    /// [`Shared`](super::Shared).
+    pub fn snapshot(&self) -> Snapshot<T, S> {
+        // elements shared with the snapshot are copied when they are taken out of the table.
+        self.clone.get_or_init(|| T::clone);
+        Snapshot {
+            data: self.data.clone(),
+            indices: self
+                .indices
+                .iter()
+                .map(|(name, index)| (name.clone(), S::index_snapshot(index)))
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use super::{Callback, Table, Threading};
+use crate::Identity;
+use std::collections::BTreeMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+/// Change made to a table, as seen by subscribers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change<T: Identity> {
+    /// Element with this primary key was inserted.
+    Inserted(T::PrimaryKey),
+    /// Element was updated, from the old to the new version.
+    Updated(T, T),
+    /// This element was removed.
+    Removed(T),
+    /// All elements were removed.
+    Cleared,
+}
+
+/// Subscribers to the changes of a table.
+pub(super) struct Subscribers<T: Identity, S: Threading> {
+    /// Makes copies of changed elements, which are handed to every subscriber.
+    clone: fn(&T) -> T,
+    channels: Vec<Sender<Change<T>>>,
+    watchers: BTreeMap<String, Box<S::Watcher<T>>>,
+}
+
+impl<T: Identity, S: Threading> Table<T, S> {
+    /// Send a change to all subscribers, if there are any.
+    ///
+    /// The change is only built when someone is listening, because that means copying elements.
+    pub(super) fn notify(&mut self, change: impl FnOnce(fn(&T) -> T, &Self) -> Change<T>) {
+        let change = match &self.subscribers {
+            Some(subscribers) => change(subscribers.clone, self),
+            None => return,
+        };
+        if let Some(subscribers) = &mut self.subscribers {
+            for watcher in subscribers.watchers.values() {
+                watcher(&change);
+            }
+
+            // channels whose receiver is gone are dropped.
+            subscribers
+                .channels
+                .retain(|channel| channel.send(change.clone_with(subscribers.clone)).is_ok());
+        }
+    }
+}
+
+impl<T: Identity> Change<T> {
+    fn clone_with(&self, clone: fn(&T) -> T) -> Self {
+        match self {
+            Change::Inserted(key) => Change::Inserted(key.clone()),
+            Change::Updated(old, new) => Change::Updated(clone(old), clone(new)),
+            Change::Removed(element) => Change::Removed(clone(element)),
+            Change::Cleared => Change::Cleared,
+        }
+    }
+}
+
+impl<T: Identity + Clone, S: Threading> Table<T, S> {
+    fn subscribers(&mut self) -> &mut Subscribers<T, S> {
+        self.subscribers.get_or_insert_with(|| Subscribers {
+            clone: T::clone,
+            channels: Vec::new(),
+            watchers: BTreeMap::new(),
+        })
+    }
+
+    /// Subscribe to the changes made to this table.
+    ///
+    /// Every insert, update, remove and clear is sent to the returned channel, in the order they
+    /// happen. Changes made inside a transaction are sent right away, if the transaction is
+    /// rolled back the changes undoing them are sent as well. The subscription ends when the
+    /// receiver is dropped.
+    pub fn subscribe(&mut self) -> Receiver<Change<T>> {
+        let (sender, receiver) = channel();
+        self.subscribers().channels.push(sender);
+        receiver
+    }
+
+    /// Add a callback that is called with every change made to this table.
+    ///
+    /// Unlike hooks, watchers cannot modify the table, and see changes in the same order as
+    /// [`Table::subscribe`].
+    pub fn watch_add(
+        &mut self,
+        name: &str,
+        watcher: impl Fn(&Change<T>) + Callback<S::Watcher<T>> + 'static,
+    ) {
+        self.subscribers()
+            .watchers
+            .insert(name.to_string(), watcher.boxed());
+    }
+
+    /// Remove a watcher from this table
+    pub fn watch_remove(&mut self, name: &str) {
+        self.subscribers().watchers.remove(name);
+    }
+}
@@ -0,0 +1,159 @@
+use super::{Change, Table};
+use crate::{Identity, Index, SharedIndex};
+use std::error::Error;
+
+/// Result of a fallible hook or of a constraint.
+type Checked = Result<(), Box<dyn Error + Send + Sync>>;
+
+/// Decides whether a [`Table`] can be shared between threads.
+///
+/// The hooks, constraints, watchers and indices of a [`Local`] table can be any closure or index,
+/// while those of a [`Shared`] table have to be `Send + Sync`, which makes the table itself and
+/// its snapshots `Send + Sync`. Only shared tables can be wrapped in a
+/// [`ConcurrentTable`](crate::ConcurrentTable).
+///
+/// The associated types are the types callbacks and indices are stored as, they are turned into
+/// them through [`Callback`].
+pub trait Threading: Sized + 'static {
+    type PreInsertHook<T: Identity>: ?Sized + Fn(&mut Table<T, Self>, &mut T) -> Checked;
+    type PreInsertHookInfallible<T: Identity>: ?Sized + Fn(&mut Table<T, Self>, &mut T);
+    type PostInsertHook<T: Identity>: ?Sized + Fn(&mut Table<T, Self>, &T::PrimaryKey);
+    type PreUpdateHook<T: Identity>: ?Sized + Fn(&mut Table<T, Self>, &T::PrimaryKey, &mut T);
+    type PostUpdateHook<T: Identity>: ?Sized + Fn(&mut Table<T, Self>, &T::PrimaryKey, &T);
+    type PreRemoveHook<T: Identity>: ?Sized + Fn(&mut Table<T, Self>, &T::PrimaryKey);
+    type PostRemoveHook<T: Identity>: ?Sized + Fn(&mut Table<T, Self>, &T);
+    type ClearHook<T: Identity>: ?Sized + Fn(&mut Table<T, Self>);
+    type Constraint<T: Identity>: ?Sized + Fn(&T) -> Checked;
+    type Watcher<T: Identity>: ?Sized + Fn(&Change<T>);
+    type Index<T: Identity>: ?Sized + Index<T>;
+
+    /// Turn a pre-insert hook into a fallible one that never fails.
+    fn pre_insert_hook_fallible<T: Identity + 'static>(
+        hook: Box<Self::PreInsertHookInfallible<T>>,
+    ) -> Box<Self::PreInsertHook<T>>;
+
+    /// Get an index as one that is not necessarily `Send + Sync`.
+    fn index_ref<T: Identity>(index: &Self::Index<T>) -> &dyn Index<T>;
+
+    /// Take a snapshot of an index, see [`Index::snapshot`].
+    fn index_snapshot<T: Identity>(index: &Self::Index<T>) -> Option<Box<Self::Index<T>>>;
+}
+
+/// Closure or index that can be stored as a callback or index of type `C`.
+///
+/// Implemented for every closure with the right signature and for every index, and for
+/// [`Shared`] tables only if the closure or index is also `Send + Sync`.
+pub trait Callback<C: ?Sized> {
+    fn boxed(self) -> Box<C>;
+}
+
+/// Closure that can be stored as a post-remove hook of type `C`, see [`Callback`].
+///
+/// Separate from [`Callback`] because post-remove hooks are called with an element rather
+/// than a primary key, which are the same type for elements that are their own primary key.
+pub trait RemovedCallback<C: ?Sized> {
+    fn boxed(self) -> Box<C>;
+}
+
+/// Table that stays on one thread, its callbacks and indices can be anything. This is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Local;
+
+/// Table that can be shared between threads, its callbacks and indices have to be `Send + Sync`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Shared;
+
+/// Implement [`Threading`] for a marker, storing callbacks with the given auto traits and
+/// indices as the given index trait, which takes snapshots with the given method.
+macro_rules! threading {
+    ($threading:ident $(: $($bound:ident),+)?; $index:ident, $snapshot:ident) => {
+        impl Threading for $threading {
+            type PreInsertHook<T: Identity> =
+                dyn Fn(&mut Table<T, $threading>, &mut T) -> Checked $($(+ $bound)+)?;
+            type PreInsertHookInfallible<T: Identity> =
+                dyn Fn(&mut Table<T, $threading>, &mut T) $($(+ $bound)+)?;
+            type PostInsertHook<T: Identity> =
+                dyn Fn(&mut Table<T, $threading>, &T::PrimaryKey) $($(+ $bound)+)?;
+            type PreUpdateHook<T: Identity> =
+                dyn Fn(&mut Table<T, $threading>, &T::PrimaryKey, &mut T) $($(+ $bound)+)?;
+            type PostUpdateHook<T: Identity> =
+                dyn Fn(&mut Table<T, $threading>, &T::PrimaryKey, &T) $($(+ $bound)+)?;
+            type PreRemoveHook<T: Identity> =
+                dyn Fn(&mut Table<T, $threading>, &T::PrimaryKey) $($(+ $bound)+)?;
+            type PostRemoveHook<T: Identity> =
+                dyn Fn(&mut Table<T, $threading>, &T) $($(+ $bound)+)?;
+            type ClearHook<T: Identity> = dyn Fn(&mut Table<T, $threading>) $($(+ $bound)+)?;
+            type Constraint<T: Identity> = dyn Fn(&T) -> Checked $($(+ $bound)+)?;
+            type Watcher<T: Identity> = dyn Fn(&Change<T>) $($(+ $bound)+)?;
+            type Index<T: Identity> = dyn $index<T>;
+
+            fn pre_insert_hook_fallible<T: Identity + 'static>(
+                hook: Box<Self::PreInsertHookInfallible<T>>,
+            ) -> Box<Self::PreInsertHook<T>> {
+                Box::new(move |table, element| {
+                    hook(table, element);
+                    Ok(())
+                })
+            }
+
+            fn index_ref<T: Identity>(index: &Self::Index<T>) -> &dyn Index<T> {
+                index
+            }
+
+            fn index_snapshot<T: Identity>(index: &Self::Index<T>) -> Option<Box<Self::Index<T>>> {
+                index.$snapshot()
+            }
+        }
+
+        threading!(@callback $threading $(: $($bound),+)?; Table<T, $threading>, &mut T => Checked);
+        threading!(@callback $threading $(: $($bound),+)?; Table<T, $threading>, &mut T);
+        threading!(@callback $threading $(: $($bound),+)?; Table<T, $threading>, &T::PrimaryKey);
+        threading!(@callback $threading $(: $($bound),+)?; Table<T, $threading>, &T::PrimaryKey, &mut T);
+        threading!(@callback $threading $(: $($bound),+)?; Table<T, $threading>, &T::PrimaryKey, &T);
+        threading!(@callback $threading $(: $($bound),+)?; Table<T, $threading>, &T as RemovedCallback);
+        threading!(@callback $threading $(: $($bound),+)?; Table<T, $threading>);
+
+        impl<T: Identity, F> Callback<dyn Fn(&T) -> Checked $($(+ $bound)+)?> for F
+        where
+            F: Fn(&T) -> Checked $($(+ $bound)+)? + 'static,
+        {
+            fn boxed(self) -> Box<dyn Fn(&T) -> Checked $($(+ $bound)+)?> {
+                Box::new(self)
+            }
+        }
+
+        impl<T: Identity, F> Callback<dyn Fn(&Change<T>) $($(+ $bound)+)?> for F
+        where
+            F: Fn(&Change<T>) $($(+ $bound)+)? + 'static,
+        {
+            fn boxed(self) -> Box<dyn Fn(&Change<T>) $($(+ $bound)+)?> {
+                Box::new(self)
+            }
+        }
+
+        impl<T: Identity, I> Callback<dyn $index<T>> for I
+        where
+            I: Index<T> $($(+ $bound)+)?,
+        {
+            fn boxed(self) -> Box<dyn $index<T>> {
+                Box::new(self)
+            }
+        }
+    };
+    (@callback $threading:ident $(: $($bound:ident),+)?; $table:ty $(, $arg:ty)* $(=> $output:ty)? as $callback:ident) => {
+        impl<T: Identity, F> $callback<dyn Fn(&mut $table $(, $arg)*) $(-> $output)? $($(+ $bound)+)?> for F
+        where
+            F: Fn(&mut $table $(, $arg)*) $(-> $output)? $($(+ $bound)+)? + 'static,
+        {
+            fn boxed(self) -> Box<dyn Fn(&mut $table $(, $arg)*) $(-> $output)? $($(+ $bound)+)?> {
+                Box::new(self)
+            }
+        }
+    };
+    (@callback $threading:ident $(: $($bound:ident),+)?; $table:ty $(, $arg:ty)* $(=> $output:ty)?) => {
+        threading!(@callback $threading $(: $($bound),+)?; $table $(, $arg)* $(=> $output)? as Callback);
+    };
+}
+
+threading!(Local; Index, snapshot);
+threading!(Shared: Send, Sync; SharedIndex, snapshot_shared);
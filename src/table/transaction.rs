@@ -0,0 +1,265 @@
+use super::foreign::Undo;
+use super::{Change, Data, Table, Threading};
+use crate::{Identity, Index};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+/// Undo log of the transactions running on a table.
+pub(super) struct Journal<T: Identity> {
+    /// Makes copies of removed and replaced elements, which are handed to the caller.
+    clone: fn(&T) -> T,
+    entries: Vec<JournalEntry<T>>,
+    /// Whether the changes are handed to the caller to be undone later, see
+    /// [`Table::transaction_undoable`].
+    pub(super) undoable: bool,
+}
+
+/// Change made to the data of a table, with everything needed to undo it.
+enum JournalEntry<T: Identity> {
+    /// Element with this primary key was inserted.
+    Inserted(T::PrimaryKey),
+    /// This element was removed.
+    Removed(T),
+    /// Element was replaced by the one with this primary key.
+    Replaced(T::PrimaryKey, T),
+    /// All of these elements were cleared.
+    Cleared(Data<T>),
+    /// Foreign keys referencing removed elements changed other tables, undone by these.
+    Referrers(Vec<Undo>),
+}
+
+/// Changes made by a transaction that was committed, which can still be undone.
+pub(crate) struct Rollback<T: Identity>(Vec<JournalEntry<T>>);
+
+/// Primary keys touched by a transaction that is about to be committed.
+pub(crate) struct Changes<K> {
+    /// Whether the table was cleared, all elements present afterwards are then changed.
+    pub(crate) cleared: bool,
+    /// Keys of the elements that were inserted, replaced or removed.
+    pub(crate) keys: BTreeSet<K>,
+}
+
+impl<T: Identity, S: Threading> Table<T, S> {
+    /// Record that the element with this primary key was inserted.
+    pub(super) fn journal_inserted(&mut self, key: &T::PrimaryKey) {
+        if let Some(journal) = &mut self.journal {
+            journal.entries.push(JournalEntry::Inserted(key.clone()));
+        }
+    }
+
+    /// Record that this element was removed.
+    pub(super) fn journal_removed(&mut self, element: &T) {
+        if let Some(journal) = &mut self.journal {
+            let element = (journal.clone)(element);
+            journal.entries.push(JournalEntry::Removed(element));
+        }
+    }
+
+    /// Record that the old element was replaced by the one with this primary key.
+    pub(super) fn journal_replaced(&mut self, key: &T::PrimaryKey, old: &T) {
+        if let Some(journal) = &mut self.journal {
+            let old = (journal.clone)(old);
+            journal
+                .entries
+                .push(JournalEntry::Replaced(key.clone(), old));
+        }
+    }
+
+    /// Record that these elements were cleared.
+    pub(super) fn journal_cleared(&mut self, data: Data<T>) {
+        if let Some(journal) = &mut self.journal {
+            journal.entries.push(JournalEntry::Cleared(data));
+        }
+    }
+
+    /// Record how to undo the changes foreign keys made to other tables.
+    pub(super) fn journal_referrers(&mut self, undos: Vec<Undo>) {
+        if let (Some(journal), false) = (&mut self.journal, undos.is_empty()) {
+            journal.entries.push(JournalEntry::Referrers(undos));
+        }
+    }
+
+    /// Collect the primary keys touched by the changes recorded after the savepoint.
+    fn journal_changes(&self, savepoint: usize) -> Changes<T::PrimaryKey> {
+        let mut changes = Changes {
+            cleared: false,
+            keys: BTreeSet::new(),
+        };
+        let entries = match &self.journal {
+            Some(journal) => &journal.entries[savepoint..],
+            None => return changes,
+        };
+        for entry in entries {
+            match entry {
+                JournalEntry::Inserted(key) => {
+                    changes.keys.insert(key.clone());
+                }
+                JournalEntry::Removed(element) => {
+                    changes.keys.insert(element.primary_key());
+                }
+                JournalEntry::Replaced(key, old) => {
+                    changes.keys.insert(key.clone());
+                    changes.keys.insert(old.primary_key());
+                }
+                JournalEntry::Cleared(_) => {
+                    // everything touched before is gone, and everything after is recorded.
+                    changes.cleared = true;
+                    changes.keys.clear();
+                }
+                JournalEntry::Referrers(_) => {}
+            }
+        }
+        changes
+    }
+}
+
+impl<T: Identity + Clone, S: Threading> Table<T, S> {
+    /// Run a closure as a transaction on this table.
+    ///
+    /// Every insert, update, remove and clear made by the closure, including the ones made by
+    /// hooks, is recorded. If the closure returns an error, all of them are undone, restoring the
+    /// data and every index to exactly the state before the transaction. Hooks are not run again
+    /// when changes are undone, and adding or removing indices, constraints or hooks is not
+    /// undone.
+    ///
+    /// Transactions can be nested, an inner transaction that fails only undoes its own changes.
+    pub fn transaction<R, E>(
+        &mut self,
+        transaction: impl FnOnce(&mut Self) -> Result<R, E>,
+    ) -> Result<R, E> {
+        self.transaction_commit(transaction, |_, _| Ok(()))
+    }
+
+    /// Run a closure as a transaction, calling `commit` with the changes before committing.
+    ///
+    /// If `commit` fails, the transaction is rolled back as well.
+    pub(crate) fn transaction_commit<R, E>(
+        &mut self,
+        transaction: impl FnOnce(&mut Self) -> Result<R, E>,
+        commit: impl FnOnce(&Self, Changes<T::PrimaryKey>) -> Result<(), E>,
+    ) -> Result<R, E> {
+        let outermost = self.journal.is_none();
+        let journal = self.journal.get_or_insert_with(|| Journal {
+            clone: T::clone,
+            entries: Vec::new(),
+            undoable: false,
+        });
+        let savepoint = journal.entries.len();
+
+        let result = transaction(self).and_then(|result| {
+            commit(self, self.journal_changes(savepoint))?;
+            Ok(result)
+        });
+        if result.is_err() {
+            self.journal_rollback(savepoint);
+        }
+        if outermost {
+            self.journal = None;
+            self.publish();
+        }
+
+        result
+    }
+
+    /// Run a closure as a transaction, returning how to undo its changes once committed.
+    ///
+    /// Foreign keys referencing removed elements are applied inside it, because undoing the
+    /// transaction undoes their changes as well. The table must not be in a transaction already.
+    pub(crate) fn transaction_undoable<R, E>(
+        &mut self,
+        transaction: impl FnOnce(&mut Self) -> Result<R, E>,
+    ) -> Result<(R, Rollback<T>), E> {
+        debug_assert!(self.journal.is_none());
+        self.journal = Some(Journal {
+            clone: T::clone,
+            entries: Vec::new(),
+            undoable: true,
+        });
+
+        let result = transaction(self);
+        if result.is_err() {
+            self.journal_rollback(0);
+        }
+        let entries = self
+            .journal
+            .take()
+            .map(|journal| journal.entries)
+            .unwrap_or_default();
+        self.publish();
+
+        result.map(|result| (result, Rollback(entries)))
+    }
+
+    /// Undo the changes of a committed transaction.
+    pub(crate) fn rollback(&mut self, rollback: Rollback<T>) {
+        self.journal = Some(Journal {
+            clone: T::clone,
+            entries: rollback.0,
+            undoable: true,
+        });
+        self.journal_rollback(0);
+        self.journal = None;
+        self.publish();
+    }
+
+    /// Undo all changes recorded after the savepoint, in reverse order.
+    fn journal_rollback(&mut self, savepoint: usize) {
+        let entries = match &mut self.journal {
+            Some(journal) => journal.entries.split_off(savepoint),
+            None => return,
+        };
+
+        // the element versions being restored were all valid before, so re-indexing them
+        // cannot fail.
+        for entry in entries.into_iter().rev() {
+            match entry {
+                JournalEntry::Inserted(key) => {
+                    if let Some(element) = self.data.remove(&key) {
+                        let element = Arc::unwrap_or_clone(element);
+                        let _ = self.indices_remove(&element);
+                        self.notify(|_, _| Change::Removed(element));
+                    }
+                }
+                JournalEntry::Removed(element) => {
+                    let key = element.primary_key();
+                    let _ = self.indices_insert(&element);
+                    self.data.insert(key.clone(), Arc::new(element));
+                    self.notify(|_, _| Change::Inserted(key));
+                }
+                JournalEntry::Replaced(key, old) => {
+                    let new = self.data.remove(&key).map(|new| Arc::unwrap_or_clone(new));
+                    if let Some(element) = &new {
+                        let _ = self.indices_remove(element);
+                    }
+                    let old_key = old.primary_key();
+                    let _ = self.indices_insert(&old);
+                    self.data.insert(old_key.clone(), Arc::new(old));
+                    self.notify(|clone, table| match new {
+                        Some(element) => Change::Updated(element, clone(&table.data[&old_key])),
+                        None => Change::Inserted(old_key),
+                    });
+                }
+                JournalEntry::Cleared(data) => {
+                    self.data = data;
+                    for index in self.indices.values_mut() {
+                        index.clear();
+                        let _ = index.insert_bulk(Box::new(self.data.values().map(Arc::as_ref)));
+                    }
+
+                    // subscribers saw the clear, so they are told about every restored element.
+                    if self.subscribers.is_some() {
+                        let keys: Vec<T::PrimaryKey> = self.data.keys().cloned().collect();
+                        for key in keys {
+                            self.notify(|_, _| Change::Inserted(key));
+                        }
+                    }
+                }
+                JournalEntry::Referrers(undos) => {
+                    for undo in undos.into_iter().rev() {
+                        undo();
+                    }
+                }
+            }
+        }
+    }
+}
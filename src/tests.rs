@@ -1,9 +1,12 @@
 use crate::*;
+#[allow(unused_imports)]
 use anyhow::anyhow;
 use rand::distributions::{Alphanumeric, DistString};
 use rand::*;
+use std::ops::Bound;
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 struct Person {
     id: u64,
     name: String,
@@ -70,6 +73,7 @@ fn can_clear_entries() {
 }
 
 #[test]
+#[allow(unused_must_use)]
 fn can_create_person_table() {
     let mut table = Table::new();
     table.insert(Person {
@@ -119,6 +123,7 @@ fn cannot_insert_duplicate_primary_key() {
 }
 
 #[test]
+#[allow(clippy::len_zero)]
 fn cannot_insert_failing_constraint() {
     let mut table = Table::new();
     table
@@ -150,6 +155,7 @@ fn cannot_insert_failing_constraint() {
 }
 
 #[test]
+#[allow(clippy::len_zero)]
 fn cannot_insert_failing_constraint_after() {
     let mut table = Table::new();
     table
@@ -178,6 +184,7 @@ fn cannot_insert_failing_constraint_after() {
 }
 
 #[test]
+#[allow(unused_variables)]
 fn cannot_insert_duplicate_unique_index() {
     let mut table = Table::new();
     table
@@ -204,6 +211,7 @@ fn cannot_insert_duplicate_unique_index() {
 }
 
 #[test]
+#[allow(unused_variables)]
 fn can_insert_multiple_unique_index() {
     let mut table = Table::new();
     table
@@ -231,6 +239,7 @@ fn can_insert_multiple_unique_index() {
 }
 
 #[test]
+#[allow(unused_variables)]
 fn can_insert_duplicate_index() {
     let mut table = Table::new();
     table
@@ -255,6 +264,7 @@ fn can_insert_duplicate_index() {
 }
 
 #[test]
+#[allow(unused_variables)]
 fn can_insert_one_many_rows() {
     let mut rng = rand::rngs::StdRng::seed_from_u64(23420292352);
     let amount = 100_000;
@@ -301,3 +311,2518 @@ fn can_insert_one_many_rows() {
 
     assert_eq!(table.len(), amount);
 }
+
+#[test]
+fn can_lookup_rows_by_index() {
+    let mut table = Table::new();
+    table
+        .index_add("name", BTreeIndex::new(|item: &Person| item.name.clone()))
+        .unwrap();
+    table
+        .insert(Person {
+            id: 0,
+            name: "Mike".into(),
+            age: 32,
+        })
+        .unwrap();
+    table
+        .insert(Person {
+            id: 1,
+            name: "John".into(),
+            age: 24,
+        })
+        .unwrap();
+    table
+        .insert(Person {
+            id: 2,
+            name: "Mike".into(),
+            age: 45,
+        })
+        .unwrap();
+
+    let ids: Vec<u64> = table
+        .index_lookup("name", &String::from("Mike"))
+        .unwrap()
+        .map(|person| person.id)
+        .collect();
+    assert_eq!(ids, vec![0, 2]);
+
+    let ids: Vec<u64> = table
+        .index_lookup("name", &String::from("Jane"))
+        .unwrap()
+        .map(|person| person.id)
+        .collect();
+    assert!(ids.is_empty());
+}
+
+#[test]
+fn cannot_lookup_unknown_index_or_wrong_key() {
+    let mut table = Table::new();
+    table
+        .index_add("name", BTreeIndex::new(|item: &Person| item.name.clone()))
+        .unwrap();
+
+    let result = table.index_lookup("age", &32u16);
+    assert!(matches!(result, Err(TableError::UnknownIndex(name)) if name == "age"));
+
+    let result = table.index_lookup("name", &32u16);
+    assert!(matches!(result, Err(TableError::KeyType(name)) if name == "name"));
+}
+
+#[test]
+fn can_lookup_rows_by_index_handle() {
+    let mut table = Table::new();
+    let by_age = table
+        .index_add("age", BTreeIndex::new(|item: &Person| item.age))
+        .unwrap();
+    let by_name = table
+        .index_add(
+            "name",
+            UniqueBTreeIndex::new(|item: &Person| item.name.clone()),
+        )
+        .unwrap();
+    table
+        .insert(Person {
+            id: 0,
+            name: "Mike".into(),
+            age: 32,
+        })
+        .unwrap();
+    table
+        .insert(Person {
+            id: 1,
+            name: "John".into(),
+            age: 32,
+        })
+        .unwrap();
+
+    assert_eq!(by_age.name(), "age");
+    assert_eq!(by_age.lookup(&table, &32).unwrap().count(), 2);
+    let ids: Vec<u64> = by_name
+        .lookup(&table, &"John".into())
+        .unwrap()
+        .map(|person| person.id)
+        .collect();
+    assert_eq!(ids, vec![1]);
+
+    // handles to removed indices fail gracefully
+    table.index_remove("age");
+    let result = by_age.lookup(&table, &32);
+    assert!(matches!(result, Err(TableError::UnknownIndex(name)) if name == "age"));
+}
+
+#[test]
+fn can_remove_entries() {
+    let mut table = Table::new();
+    let by_name = table
+        .index_add(
+            "name",
+            UniqueBTreeIndex::new(|item: &Person| item.name.clone()),
+        )
+        .unwrap();
+    table
+        .insert(Person {
+            id: 0,
+            name: "Mike".into(),
+            age: 32,
+        })
+        .unwrap();
+
+    let removed = table.remove(&0).unwrap();
+    assert_eq!(removed.name, "Mike");
+    assert_eq!(table.len(), 0);
+    assert!(table.remove(&0).is_none());
+    assert_eq!(by_name.lookup(&table, &"Mike".into()).unwrap().count(), 0);
+
+    // unique index no longer blocks the name
+    table
+        .insert(Person {
+            id: 1,
+            name: "Mike".into(),
+            age: 32,
+        })
+        .unwrap();
+}
+
+#[test]
+fn can_retain_entries() {
+    let mut table = Table::new();
+    let by_age = table
+        .index_add("age", BTreeIndex::new(|item: &Person| item.age))
+        .unwrap();
+    for id in 0..10 {
+        table
+            .insert(Person {
+                id,
+                name: format!("Person {id}"),
+                age: 20 + (id % 2) as u16,
+            })
+            .unwrap();
+    }
+
+    table.retain(|person| person.age == 20);
+    assert_eq!(table.len(), 5);
+    assert_eq!(by_age.lookup(&table, &20).unwrap().count(), 5);
+    assert_eq!(by_age.lookup(&table, &21).unwrap().count(), 0);
+}
+
+#[test]
+fn can_remove_entries_that_cannot_be_cloned() {
+    #[derive(Debug)]
+    struct Ticket {
+        id: u64,
+        seat: u16,
+    }
+
+    impl Identity for Ticket {
+        type PrimaryKey = u64;
+        fn primary_key(&self) -> Self::PrimaryKey {
+            self.id
+        }
+    }
+
+    let mut table = Table::new();
+    let by_seat = table
+        .index_add("seat", BTreeIndex::new(|item: &Ticket| item.seat))
+        .unwrap();
+    for id in 0..10 {
+        table
+            .insert(Ticket {
+                id,
+                seat: id as u16,
+            })
+            .unwrap();
+    }
+
+    let removed = table.remove(&0).unwrap();
+    assert_eq!(removed.seat, 0);
+    table.upsert(Ticket { id: 1, seat: 20 }).unwrap();
+    table.retain(|ticket| ticket.seat >= 5);
+    assert_eq!(table.len(), 6);
+    assert_eq!(by_seat.lookup(&table, &1).unwrap().count(), 0);
+    assert_eq!(by_seat.lookup(&table, &20).unwrap().count(), 1);
+}
+
+#[test]
+fn duplicate_in_later_index_rolls_back_earlier_indices() {
+    let mut table = Table::new();
+    let by_age = table
+        .index_add("age", UniqueBTreeIndex::new(|item: &Person| item.age))
+        .unwrap();
+    table
+        .index_add(
+            "name",
+            UniqueBTreeIndex::new(|item: &Person| item.name.clone()),
+        )
+        .unwrap();
+    table
+        .insert(Person {
+            id: 0,
+            name: "Mike".into(),
+            age: 32,
+        })
+        .unwrap();
+
+    let result = table.insert(Person {
+        id: 1,
+        name: "Mike".into(),
+        age: 24,
+    });
+    assert!(matches!(result, Err(TableError::Duplicate(name, 0)) if name == "name"));
+    assert_eq!(by_age.lookup(&table, &24).unwrap().count(), 0);
+}
+
+#[test]
+fn can_update_entries() {
+    let mut table = Table::new();
+    let by_age = table
+        .index_add("age", BTreeIndex::new(|item: &Person| item.age))
+        .unwrap();
+    table
+        .insert(Person {
+            id: 0,
+            name: "Mike".into(),
+            age: 32,
+        })
+        .unwrap();
+
+    let key = table.update(&0, |person| person.age = 33).unwrap();
+    assert_eq!(key, 0);
+    assert_eq!(table.lookup(&0).unwrap().age, 33);
+    assert_eq!(by_age.lookup(&table, &32).unwrap().count(), 0);
+    assert_eq!(by_age.lookup(&table, &33).unwrap().count(), 1);
+
+    let result = table.update(&1, |person| person.age = 33);
+    assert!(matches!(result, Err(TableError::NotFound(1))));
+}
+
+#[test]
+fn failed_update_keeps_old_version() {
+    let mut table = Table::new();
+    table
+        .constraint_add("age", |item: &Person| {
+            if item.age > 100 {
+                Err(MyError::Fail)?
+            } else {
+                Ok(())
+            }
+        })
+        .unwrap();
+    let by_name = table
+        .index_add(
+            "name",
+            UniqueBTreeIndex::new(|item: &Person| item.name.clone()),
+        )
+        .unwrap();
+    for (id, name) in ["Mike", "John"].into_iter().enumerate() {
+        table
+            .insert(Person {
+                id: id as u64,
+                name: name.into(),
+                age: 32,
+            })
+            .unwrap();
+    }
+
+    let result = table.update(&0, |person| person.age = 200);
+    assert!(matches!(result, Err(TableError::Constraint(_, _))));
+    let result = table.update(&0, |person| person.name = "John".into());
+    assert!(matches!(result, Err(TableError::Duplicate(_, 1))));
+    let result = table.update(&0, |person| person.id = 1);
+    assert!(matches!(result, Err(TableError::Exists(1))));
+
+    assert_eq!(table.lookup(&0).unwrap().name, "Mike");
+    assert_eq!(table.lookup(&0).unwrap().age, 32);
+    let ids: Vec<u64> = by_name
+        .lookup(&table, &"Mike".into())
+        .unwrap()
+        .map(|person| person.id)
+        .collect();
+    assert_eq!(ids, vec![0]);
+}
+
+#[test]
+fn can_upsert_entries() {
+    let mut table = Table::new();
+    let by_name = table
+        .index_add(
+            "name",
+            UniqueBTreeIndex::new(|item: &Person| item.name.clone()),
+        )
+        .unwrap();
+    table
+        .upsert(Person {
+            id: 0,
+            name: "Mike".into(),
+            age: 32,
+        })
+        .unwrap();
+    table
+        .upsert(Person {
+            id: 0,
+            name: "Michael".into(),
+            age: 33,
+        })
+        .unwrap();
+
+    assert_eq!(table.len(), 1);
+    assert_eq!(table.lookup(&0).unwrap().name, "Michael");
+    assert_eq!(by_name.lookup(&table, &"Mike".into()).unwrap().count(), 0);
+    assert_eq!(
+        by_name.lookup(&table, &"Michael".into()).unwrap().count(),
+        1
+    );
+}
+
+#[test]
+fn duplicate_primary_key_keeps_index_intact() {
+    let mut table = Table::new();
+    let by_name = table
+        .index_add("name", BTreeIndex::new(|item: &Person| item.name.clone()))
+        .unwrap();
+    let person = Person {
+        id: 0,
+        name: "Mike".into(),
+        age: 32,
+    };
+    table.insert(person.clone()).unwrap();
+    assert!(table.insert(person).is_err());
+    assert_eq!(by_name.lookup(&table, &"Mike".into()).unwrap().count(), 1);
+}
+
+#[test]
+fn can_lookup_rows_by_hash_index() {
+    let mut table = Table::new();
+    let by_name = table
+        .index_add("name", HashIndex::new(|item: &Person| item.name.clone()))
+        .unwrap();
+    for (id, name) in ["Mike", "John", "Mike"].into_iter().enumerate() {
+        table
+            .insert(Person {
+                id: id as u64,
+                name: name.into(),
+                age: 32,
+            })
+            .unwrap();
+    }
+
+    let ids: Vec<u64> = by_name
+        .lookup(&table, &"Mike".into())
+        .unwrap()
+        .map(|person| person.id)
+        .collect();
+    assert_eq!(ids, vec![0, 2]);
+
+    table.remove(&0);
+    assert_eq!(by_name.lookup(&table, &"Mike".into()).unwrap().count(), 1);
+}
+
+#[test]
+fn cannot_insert_duplicate_unique_hash_index() {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::BuildHasherDefault;
+
+    let mut table = Table::new();
+    let by_name = table
+        .index_add(
+            "name",
+            UniqueHashIndex::with_hasher(
+                |item: &Person| item.name.clone(),
+                BuildHasherDefault::<DefaultHasher>::default(),
+            ),
+        )
+        .unwrap();
+    table
+        .insert(Person {
+            id: 0,
+            name: "Mike".into(),
+            age: 32,
+        })
+        .unwrap();
+
+    let result = table.insert(Person {
+        id: 1,
+        name: "Mike".into(),
+        age: 32,
+    });
+    assert!(matches!(result, Err(TableError::Duplicate(_, 0))));
+    assert_eq!(by_name.lookup(&table, &"Mike".into()).unwrap().count(), 1);
+}
+
+#[test]
+fn can_query_index_ranges() {
+    let mut table = Table::new();
+    let by_age = table
+        .index_add("age", BTreeIndex::new(|item: &Person| item.age))
+        .unwrap();
+    let by_name = table
+        .index_add(
+            "name",
+            UniqueBTreeIndex::new(|item: &Person| item.name.clone()),
+        )
+        .unwrap();
+    let by_hash = table
+        .index_add("hash", HashIndex::new(|item: &Person| item.age))
+        .unwrap();
+    for (id, age) in [12, 18, 25, 30, 31, 45].into_iter().enumerate() {
+        table
+            .insert(Person {
+                id: id as u64,
+                name: format!("Person {id}"),
+                age,
+            })
+            .unwrap();
+    }
+
+    let ages: Vec<u16> = by_age
+        .range(&table, 18..=30)
+        .unwrap()
+        .map(|person| person.age)
+        .collect();
+    assert_eq!(ages, vec![18, 25, 30]);
+
+    let ages: Vec<u16> = by_age
+        .range(&table, 18..30)
+        .unwrap()
+        .rev()
+        .map(|person| person.age)
+        .collect();
+    assert_eq!(ages, vec![25, 18]);
+
+    let ids: Vec<u64> = by_name
+        .range(&table, "Person 4".to_string()..)
+        .unwrap()
+        .map(|person| person.id)
+        .collect();
+    assert_eq!(ids, vec![4, 5]);
+
+    // inverted ranges are empty rather than panicking
+    #[allow(clippy::reversed_empty_ranges)]
+    let result = by_age.range(&table, 30..18).unwrap().count();
+    assert_eq!(result, 0);
+
+    // hash indices have no ordering
+    let result = by_hash.range(&table, 18..30);
+    assert!(matches!(result, Err(TableError::Unsupported(name)) if name == "hash"));
+}
+
+#[test]
+fn can_insert_many_rows() {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(23420292352);
+    let amount = 100_000;
+    let mut table = Table::new();
+
+    // auto-increment primary key, the batch is only visible in the table once it is inserted
+    let next = std::sync::atomic::AtomicU64::new(0);
+    table.pre_insert_hook_add("primary_key", move |_: &mut Table<Person>, item| {
+        item.id = next.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    });
+
+    // add unique name index
+    let by_name = table
+        .index_add(
+            "name",
+            UniqueBTreeIndex::new(|item: &Person| item.name.clone()),
+        )
+        .unwrap();
+
+    // add age index
+    let by_age = table
+        .index_add("age", BTreeIndex::new(|item: &Person| item.age))
+        .unwrap();
+
+    let people: Vec<Person> = (0..amount)
+        .map(|_| Person {
+            id: 0,
+            name: Alphanumeric.sample_string(&mut rng, 16),
+            age: rng.gen_range(0..100),
+        })
+        .collect();
+    let first = people[0].name.clone();
+    let keys = table.insert_many(people).unwrap();
+
+    assert_eq!(keys.len(), amount);
+    assert_eq!(table.len(), amount);
+    assert_eq!(by_age.range(&table, ..).unwrap().count(), amount);
+    let ids: Vec<u64> = by_name
+        .lookup(&table, &first)
+        .unwrap()
+        .map(|person| person.id)
+        .collect();
+    assert_eq!(ids, vec![0]);
+}
+
+#[test]
+fn insert_many_is_all_or_nothing() {
+    let mut table = Table::new();
+    let by_age = table
+        .index_add("age", BTreeIndex::new(|item: &Person| item.age))
+        .unwrap();
+    table
+        .index_add(
+            "name",
+            UniqueHashIndex::new(|item: &Person| item.name.clone()),
+        )
+        .unwrap();
+    table
+        .insert(Person {
+            id: 0,
+            name: "Mike".into(),
+            age: 32,
+        })
+        .unwrap();
+
+    // duplicate name within the batch
+    let result = table.insert_many([
+        Person {
+            id: 1,
+            name: "John".into(),
+            age: 24,
+        },
+        Person {
+            id: 2,
+            name: "John".into(),
+            age: 24,
+        },
+    ]);
+    assert!(matches!(result, Err(TableError::Duplicate(name, 1)) if name == "name"));
+
+    // duplicate primary key against the table
+    let result = table.insert_many([
+        Person {
+            id: 3,
+            name: "Jane".into(),
+            age: 24,
+        },
+        Person {
+            id: 0,
+            name: "Mary".into(),
+            age: 24,
+        },
+    ]);
+    assert!(matches!(result, Err(TableError::Exists(0))));
+
+    // duplicate primary key within the batch
+    let result = table.insert_many([
+        Person {
+            id: 3,
+            name: "Jane".into(),
+            age: 24,
+        },
+        Person {
+            id: 3,
+            name: "Mary".into(),
+            age: 24,
+        },
+    ]);
+    assert!(matches!(result, Err(TableError::Exists(3))));
+
+    assert_eq!(table.len(), 1);
+    assert_eq!(by_age.lookup(&table, &24).unwrap().count(), 0);
+    table
+        .insert(Person {
+            id: 1,
+            name: "John".into(),
+            age: 24,
+        })
+        .unwrap();
+}
+
+#[test]
+fn insert_many_survives_hooks_removing_rows() {
+    let mut table = Table::new();
+    let by_age = table
+        .index_add("age", BTreeIndex::new(|item: &Person| item.age))
+        .unwrap();
+    table
+        .insert(Person {
+            id: 0,
+            name: "Mike".into(),
+            age: 32,
+        })
+        .unwrap();
+
+    // the hook clears the table, batch rows must not be visible to it yet
+    table.pre_insert_hook_add("clear", |table: &mut Table<Person>, _| {
+        assert!(table.lookup(&1).is_none());
+        table.clear();
+    });
+    let keys = table
+        .insert_many([
+            Person {
+                id: 1,
+                name: "John".into(),
+                age: 24,
+            },
+            Person {
+                id: 2,
+                name: "Jane".into(),
+                age: 24,
+            },
+        ])
+        .unwrap();
+
+    assert_eq!(keys, vec![1, 2]);
+    assert_eq!(table.len(), 2);
+    assert_eq!(by_age.lookup(&table, &24).unwrap().count(), 2);
+    assert_eq!(by_age.lookup(&table, &32).unwrap().count(), 0);
+}
+
+#[test]
+fn insert_many_keeps_hook_changes_unless_in_transaction() {
+    let mut table = Table::new();
+    table
+        .insert(Person {
+            id: 0,
+            name: "Mike".into(),
+            age: 32,
+        })
+        .unwrap();
+    table.pre_insert_hook_add("clear", |table: &mut Table<Person>, _| table.clear());
+    let batch = || {
+        [1, 1].map(|id| Person {
+            id,
+            name: "Mary".into(),
+            age: 40,
+        })
+    };
+
+    let result = table.transaction(|table| table.insert_many(batch()));
+    assert!(matches!(result, Err(TableError::Exists(1))));
+    assert_eq!(table.len(), 1);
+    let result = table.insert_many(batch());
+    assert!(matches!(result, Err(TableError::Exists(1))));
+    assert!(table.is_empty());
+}
+
+#[test]
+fn cannot_add_unique_index_over_duplicates() {
+    let mut table = Table::new();
+    for id in 0..2 {
+        table
+            .insert(Person {
+                id,
+                name: "Mike".into(),
+                age: 32,
+            })
+            .unwrap();
+    }
+
+    let result = table.index_add(
+        "name",
+        UniqueBTreeIndex::new(|item: &Person| item.name.clone()),
+    );
+    assert!(matches!(result, Err(TableError::Duplicate(name, 0)) if name == "name"));
+    assert!(matches!(
+        table.index_lookup("name", &String::from("Mike")),
+        Err(TableError::UnknownIndex(_))
+    ));
+}
+
+#[test]
+fn can_hook_into_every_mutation() {
+    use std::sync::{Arc, Mutex};
+
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut table: Table<Person> = Table::new();
+
+    let events = log.clone();
+    table.post_insert_hook_add("log", move |_, key| {
+        events.lock().unwrap().push(format!("inserted {key}"));
+    });
+    let events = log.clone();
+    table.pre_update_hook_add("log", move |table, key, new| {
+        let old = table.lookup(key).unwrap();
+        events
+            .lock()
+            .unwrap()
+            .push(format!("updating {} to {}", old.name, new.name));
+    });
+    let events = log.clone();
+    table.post_update_hook_add("log", move |_, key, old| {
+        events
+            .lock()
+            .unwrap()
+            .push(format!("updated {key} from {}", old.name));
+    });
+    let events = log.clone();
+    table.pre_remove_hook_add("log", move |_, key| {
+        events.lock().unwrap().push(format!("removing {key}"));
+    });
+    let events = log.clone();
+    table.post_remove_hook_add("log", move |_, old| {
+        events.lock().unwrap().push(format!("removed {}", old.name));
+    });
+    let events = log.clone();
+    table.pre_clear_hook_add("log", move |table| {
+        events
+            .lock()
+            .unwrap()
+            .push(format!("clearing {}", table.len()));
+    });
+    let events = log.clone();
+    table.post_clear_hook_add("log", move |table| {
+        events
+            .lock()
+            .unwrap()
+            .push(format!("cleared {}", table.len()));
+    });
+
+    for (id, name) in ["Mike", "John"].into_iter().enumerate() {
+        table
+            .insert(Person {
+                id: id as u64,
+                name: name.into(),
+                age: 32,
+            })
+            .unwrap();
+    }
+    table
+        .update(&0, |person| person.name = "Michael".into())
+        .unwrap();
+    table.remove(&1);
+    table.remove(&1);
+    table.clear();
+
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![
+            "inserted 0",
+            "inserted 1",
+            "updating Mike to Michael",
+            "updated 0 from Mike",
+            "removing 1",
+            "removed John",
+            "clearing 1",
+            "cleared 0",
+        ]
+    );
+}
+
+#[test]
+fn can_remove_hooks() {
+    let mut table = Table::new();
+    table.pre_insert_hook_add("age", |_: &mut Table<Person>, item| {
+        item.age += 1;
+    });
+    table.post_insert_hook_add("remove", |table, key| {
+        table.remove(key);
+    });
+    table
+        .insert(Person {
+            id: 0,
+            name: "Mike".into(),
+            age: 32,
+        })
+        .unwrap();
+    assert_eq!(table.len(), 0);
+
+    table.pre_insert_hook_remove("age");
+    table.post_insert_hook_remove("remove");
+    table
+        .insert(Person {
+            id: 0,
+            name: "Mike".into(),
+            age: 32,
+        })
+        .unwrap();
+    assert_eq!(table.lookup(&0).unwrap().age, 32);
+}
+
+#[test]
+fn failing_pre_insert_hook_rejects_insert() {
+    let mut table = Table::new();
+    let by_name = table
+        .index_add("name", BTreeIndex::new(|item: &Person| item.name.clone()))
+        .unwrap();
+    table.pre_insert_hook_try_add("name", |_: &mut Table<Person>, item| {
+        if item.name.is_empty() {
+            Err(MyError::Fail)?
+        }
+        item.name = item.name.to_lowercase();
+        Ok(())
+    });
+
+    table
+        .insert(Person {
+            id: 0,
+            name: "Mike".into(),
+            age: 32,
+        })
+        .unwrap();
+    assert_eq!(table.lookup(&0).unwrap().name, "mike");
+
+    let result = table.insert(Person {
+        id: 1,
+        name: "".into(),
+        age: 32,
+    });
+    assert!(matches!(result, Err(TableError::Hook(name, _)) if name == "name"));
+    assert_eq!(table.len(), 1);
+    assert_eq!(by_name.lookup(&table, &"".into()).unwrap().count(), 0);
+
+    let result = table.insert_many([
+        Person {
+            id: 2,
+            name: "John".into(),
+            age: 32,
+        },
+        Person {
+            id: 3,
+            name: "".into(),
+            age: 32,
+        },
+    ]);
+    assert!(matches!(result, Err(TableError::Hook(_, _))));
+    assert_eq!(table.len(), 1);
+}
+
+#[test]
+fn can_commit_transaction() {
+    let mut table = Table::new();
+    let by_age = table
+        .index_add("age", BTreeIndex::new(|item: &Person| item.age))
+        .unwrap();
+
+    let result: Result<(), TableError<Person>> = table.transaction(|table| {
+        table.insert(Person {
+            id: 0,
+            name: "Mike".into(),
+            age: 32,
+        })?;
+        table.insert(Person {
+            id: 1,
+            name: "John".into(),
+            age: 32,
+        })?;
+        table.update(&1, |person| person.age = 24)?;
+        Ok(())
+    });
+    result.unwrap();
+
+    assert_eq!(table.len(), 2);
+    assert_eq!(by_age.lookup(&table, &32).unwrap().count(), 1);
+    assert_eq!(by_age.lookup(&table, &24).unwrap().count(), 1);
+}
+
+#[test]
+fn failed_transaction_is_rolled_back() {
+    let mut table = Table::new();
+    let by_age = table
+        .index_add("age", BTreeIndex::new(|item: &Person| item.age))
+        .unwrap();
+    let by_name = table
+        .index_add(
+            "name",
+            UniqueBTreeIndex::new(|item: &Person| item.name.clone()),
+        )
+        .unwrap();
+    for (id, name) in ["Mike", "John", "Jane"].into_iter().enumerate() {
+        table
+            .insert(Person {
+                id: id as u64,
+                name: name.into(),
+                age: 32,
+            })
+            .unwrap();
+    }
+
+    // removing a row also removes the row it references
+    table.post_remove_hook_add("cascade", |table, person| {
+        if person.id == 2 {
+            table.remove(&1);
+        }
+    });
+
+    let result: Result<(), TableError<Person>> = table.transaction(|table| {
+        table.remove(&2);
+        table.update(&0, |person| {
+            person.name = "Michael".into();
+            person.age = 45;
+        })?;
+        table.insert(Person {
+            id: 3,
+            name: "John".into(),
+            age: 18,
+        })?;
+        table.clear();
+        table.insert(Person {
+            id: 4,
+            name: "Mary".into(),
+            age: 18,
+        })?;
+        table.insert(Person {
+            id: 5,
+            name: "Mary".into(),
+            age: 18,
+        })?;
+        Ok(())
+    });
+    assert!(matches!(result, Err(TableError::Duplicate(name, 4)) if name == "name"));
+
+    assert_eq!(table.len(), 3);
+    for (id, name) in ["Mike", "John", "Jane"].into_iter().enumerate() {
+        let ids: Vec<u64> = by_name
+            .lookup(&table, &name.into())
+            .unwrap()
+            .map(|person| person.id)
+            .collect();
+        assert_eq!(ids, vec![id as u64]);
+    }
+    assert_eq!(by_age.lookup(&table, &32).unwrap().count(), 3);
+    assert_eq!(by_age.range(&table, ..).unwrap().count(), 3);
+    assert_eq!(by_name.range(&table, ..).unwrap().count(), 3);
+}
+
+#[test]
+fn failed_nested_transaction_keeps_outer_changes() {
+    let mut table = Table::new();
+    let result: Result<(), TableError<Person>> = table.transaction(|table| {
+        table.insert(Person {
+            id: 0,
+            name: "Mike".into(),
+            age: 32,
+        })?;
+        let inner: Result<(), TableError<Person>> = table.transaction(|table| {
+            table.insert(Person {
+                id: 1,
+                name: "John".into(),
+                age: 32,
+            })?;
+            table.insert(Person {
+                id: 0,
+                name: "Mike".into(),
+                age: 32,
+            })?;
+            Ok(())
+        });
+        assert!(matches!(inner, Err(TableError::Exists(0))));
+        Ok(())
+    });
+    result.unwrap();
+
+    assert_eq!(table.len(), 1);
+    assert!(table.lookup(&0).is_some());
+    assert!(table.lookup(&1).is_none());
+}
+
+#[test]
+fn can_lookup_composite_index_by_prefix() {
+    let mut table = Table::new();
+    let by_name_age = table
+        .index_add(
+            "name_age",
+            CompositeBTreeIndex::new(|item: &Person| (item.name.clone(), item.age)),
+        )
+        .unwrap();
+    for (id, (name, age)) in [("Mike", 45), ("Jane", 18), ("Mike", 32), ("Mike", 18)]
+        .into_iter()
+        .enumerate()
+    {
+        table
+            .insert(Person {
+                id: id as u64,
+                name: name.into(),
+                age,
+            })
+            .unwrap();
+    }
+
+    let ids = |rows: Box<dyn Iterator<Item = &Person> + '_>| -> Vec<u64> {
+        rows.map(|person| person.id).collect()
+    };
+    assert_eq!(
+        ids(by_name_age.lookup(&table, &("Mike".into(), 32)).unwrap()),
+        vec![2]
+    );
+    assert_eq!(
+        ids(by_name_age.prefix(&table, &"Mike".into()).unwrap()),
+        vec![3, 2, 0]
+    );
+    assert_eq!(
+        ids(Box::new(
+            by_name_age
+                .prefix_range(&table, &"Mike".into(), 20..)
+                .unwrap()
+                .rev()
+        )),
+        vec![0, 2]
+    );
+    assert_eq!(
+        ids(Box::new(
+            by_name_age
+                .range(&table, ("Jane".to_string(), 20)..=("Mike".to_string(), 32))
+                .unwrap()
+        )),
+        vec![3, 2]
+    );
+    assert_eq!(
+        ids(table.index_lookup("name_age", &"Jane".to_string()).unwrap()),
+        vec![1]
+    );
+
+    table.remove(&1);
+    assert_eq!(
+        by_name_age.prefix(&table, &"Jane".into()).unwrap().count(),
+        0
+    );
+}
+
+#[test]
+fn can_query_multiple_indices() {
+    let mut table = Table::new();
+    let name = table
+        .index_add("name", HashIndex::new(|item: &Person| item.name.clone()))
+        .unwrap();
+    let age = table
+        .index_add("age", BTreeIndex::new(|item: &Person| item.age))
+        .unwrap();
+    for (id, (name, age)) in [
+        ("Mike", 45),
+        ("Jane", 18),
+        ("Mike", 25),
+        ("John", 19),
+        ("Mike", 18),
+        ("John", 60),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        table
+            .insert(Person {
+                id: id as u64,
+                name: name.into(),
+                age,
+            })
+            .unwrap();
+    }
+
+    let ids = |query: Query<Person>| -> Vec<u64> {
+        query.run().unwrap().map(|person| person.id).collect()
+    };
+    assert_eq!(
+        ids(table.query().eq(&name, "Mike").range(&age, 18..30)),
+        vec![2, 4]
+    );
+    assert_eq!(
+        ids(table
+            .query()
+            .any(|any| any.eq(&name, "Mike").eq(&name, "John"))
+            .range(&age, ..50)
+            .filter(|person| person.id > 2)
+            .limit(1)),
+        vec![3]
+    );
+    assert_eq!(
+        ids(table.query().filter(|person| person.age >= 45)),
+        vec![0, 5]
+    );
+    assert_eq!(
+        ids(table
+            .query()
+            .any(|any| any.eq(&name, "Jane").all(|all| all))),
+        vec![0, 1, 2, 3, 4, 5]
+    );
+    assert!(matches!(
+        table.query().range(&name, "A".to_string()..).run(),
+        Err(TableError::Unsupported(name)) if name == "name"
+    ));
+
+    let snapshot = table.snapshot();
+    table.clear();
+    assert_eq!(ids(snapshot.query().range(&age, 40..)), vec![0, 5]);
+
+    // the index of a handle can be replaced by one with another key type
+    table.index_remove("age");
+    table
+        .index_add("age", HashIndex::new(|item: &Person| item.name.clone()))
+        .unwrap();
+    assert!(matches!(
+        table.query().eq(&age, 45u16).run(),
+        Err(TableError::UnknownIndex(name)) if name == "age"
+    ));
+}
+
+#[test]
+fn query_probes_most_selective_index() {
+    let mut table = Table::new();
+    let name = table
+        .index_add("name", HashIndex::new(|item: &Person| item.name.clone()))
+        .unwrap();
+    let age = table
+        .index_add("age", BTreeIndex::new(|item: &Person| item.age))
+        .unwrap();
+    for id in 0..100 {
+        table
+            .insert(Person {
+                id,
+                name: format!("Person {}", id % 50),
+                age: if id < 90 { 32 } else { 60 },
+            })
+            .unwrap();
+    }
+
+    let stats = |name: &str| table.index(name).unwrap().stats().unwrap();
+    assert_eq!(
+        stats("name"),
+        IndexStats {
+            keys: 50,
+            entries: 100
+        }
+    );
+    assert_eq!(
+        stats("age"),
+        IndexStats {
+            keys: 2,
+            entries: 100
+        }
+    );
+    assert_eq!(stats("age").average(), 50.0);
+
+    let query = table.query().eq(&age, 32u16).eq(&name, "Person 7");
+    assert_eq!(
+        query.explain().unwrap(),
+        "check age = ?\n  probe name = ? (estimated 2 elements)"
+    );
+    let ids: Vec<u64> = query.run().unwrap().map(|person| person.id).collect();
+    assert_eq!(ids, vec![7, 57]);
+
+    let query = table
+        .query()
+        .any(|any| any.eq(&name, "Person 1").range(&age, 50..))
+        .limit(3);
+    assert_eq!(
+        query.explain().unwrap(),
+        "limit 3\n  union\n    probe name = ? (estimated 2 elements)\n    probe age in range (estimated 33 elements)"
+    );
+    let ids: Vec<u64> = query.run().unwrap().map(|person| person.id).collect();
+    assert_eq!(ids, vec![1, 51, 90]);
+    assert_eq!(table.query().explain().unwrap(), "scan");
+
+    table.remove(&7);
+    let stats = |name: &str| table.index(name).unwrap().stats().unwrap();
+    assert_eq!(
+        stats("name"),
+        IndexStats {
+            keys: 50,
+            entries: 99
+        }
+    );
+    table.clear();
+    let stats = |name: &str| table.index(name).unwrap().stats().unwrap();
+    assert_eq!(
+        stats("age"),
+        IndexStats {
+            keys: 0,
+            entries: 0
+        }
+    );
+}
+
+#[test]
+fn can_iterate_and_paginate() {
+    let mut table = Table::new();
+    for id in [5, 3, 8, 1, 9, 2] {
+        table
+            .insert(Person {
+                id,
+                name: format!("Person {id}"),
+                age: 20,
+            })
+            .unwrap();
+    }
+
+    let ids: Vec<u64> = table.iter().map(|person| person.id).collect();
+    assert_eq!(ids, vec![1, 2, 3, 5, 8, 9]);
+    assert_eq!(
+        table.keys().rev().copied().collect::<Vec<_>>(),
+        vec![9, 8, 5, 3, 2, 1]
+    );
+    let ids: Vec<u64> = table.range(2..=5).map(|person| person.id).collect();
+    assert_eq!(ids, vec![2, 3, 5]);
+    assert_eq!(
+        table
+            .range((Bound::Included(5), Bound::Excluded(2)))
+            .count(),
+        0
+    );
+
+    let mut pages = Vec::new();
+    let mut page: Vec<u64> = table.iter().take(4).map(|person| person.id).collect();
+    while let Some(&last) = page.last() {
+        pages.push(page);
+        page = table.after(&last).take(4).map(|person| person.id).collect();
+    }
+    assert_eq!(pages, vec![vec![1, 2, 3, 5], vec![8, 9]]);
+    assert_eq!(table.after(&4).next().unwrap().id, 5);
+}
+
+#[test]
+fn can_iterate_mutably() {
+    let mut table = Table::new();
+    let age = table
+        .index_add("age", UniqueBTreeIndex::new(|item: &Person| item.age))
+        .unwrap();
+    for id in 0..4 {
+        table
+            .insert(Person {
+                id,
+                name: format!("Person {id}"),
+                age: 20 + id as u16,
+            })
+            .unwrap();
+    }
+
+    let mut rows = table.iter_mut();
+    let mut visited = Vec::new();
+    while let Some(mut row) = rows.next() {
+        visited.push(*row.key());
+        if row.id % 2 == 1 {
+            row.age += 10;
+        }
+    }
+    assert_eq!(visited, vec![0, 1, 2, 3]);
+    assert_eq!(table.lookup(&1).unwrap().age, 31);
+    assert_eq!(table.lookup(&2).unwrap().age, 22);
+    assert_eq!(age.lookup(&table, &33).unwrap().next().unwrap().id, 3);
+    assert!(age.lookup(&table, &23).unwrap().next().is_none());
+
+    let mut rows = table.iter_mut();
+    let mut row = rows.next().unwrap();
+    row.age = 22;
+    assert!(matches!(row.commit(), Err(TableError::Duplicate(name, 2)) if name == "age"));
+    assert_eq!(table.lookup(&0).unwrap().age, 20);
+
+    // rows failing to write back when dropped are reported at the end
+    let mut rows = table.iter_mut();
+    while let Some(mut row) = rows.next() {
+        row.age = 22;
+    }
+    let errors = rows.finish().unwrap_err();
+    assert_eq!(errors.len(), 3);
+    assert!(errors
+        .iter()
+        .all(|(_, error)| matches!(error, TableError::Duplicate(name, 2) if name == "age")));
+    assert_eq!(
+        errors.iter().map(|(key, _)| *key).collect::<Vec<_>>(),
+        vec![0, 1, 3]
+    );
+    assert_eq!(table.lookup(&0).unwrap().age, 20);
+    assert!(table.iter_mut().finish().is_ok());
+}
+
+#[test]
+fn can_search_text_index() {
+    let mut table = Table::new();
+    let text = table
+        .index_add(
+            "text",
+            TextIndex::with_tokenizer(
+                |item: &Person| vec![item.name.clone()],
+                Stem(Lowercase(Whitespace)),
+            ),
+        )
+        .unwrap();
+    for (id, name) in [
+        "Indexing tables in memory",
+        "Tables, tables and more tables",
+        "A memory table with indexes",
+        "Running in memory",
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        table
+            .insert(Person {
+                id: id as u64,
+                name: name.into(),
+                age: 32,
+            })
+            .unwrap();
+    }
+
+    let ids = |table: &Table<Person>, query: TextQuery| -> Vec<u64> {
+        text.lookup(table, &query)
+            .unwrap()
+            .map(|person| person.id)
+            .collect()
+    };
+    assert_eq!(ids(&table, TextQuery::all("memory table")), vec![0, 2]);
+    assert_eq!(ids(&table, TextQuery::all("INDEXED")), vec![0, 2]);
+    assert!(ids(&table, TextQuery::all("memory missing")).is_empty());
+    assert_eq!(ids(&table, TextQuery::any("run indexes")), vec![0, 2, 3]);
+    assert_eq!(ids(&table, TextQuery::phrase("in memory")), vec![0, 3]);
+    assert!(ids(&table, TextQuery::phrase("memory in")).is_empty());
+
+    let ranked: Vec<u64> = text
+        .search(&table, &TextQuery::any("tables"))
+        .unwrap()
+        .into_iter()
+        .map(|(person, _)| person.id)
+        .collect();
+    assert_eq!(ranked, vec![1, 0, 2]);
+
+    table.remove(&1);
+    assert!(ids(&table, TextQuery::any("more")).is_empty());
+    assert!(matches!(
+        table.index_lookup("text", &"memory".to_string()),
+        Err(TableError::KeyType(name)) if name == "text"
+    ));
+}
+
+#[test]
+fn can_search_partial_text_index_in_snapshot() {
+    let mut table = Table::new();
+    let text = table
+        .index_add(
+            "text",
+            PartialIndex::new(
+                TextIndex::new(|item: &Person| vec![item.name.clone()]),
+                |item: &Person| item.age >= 18,
+            ),
+        )
+        .unwrap();
+    for (id, (name, age)) in [("memory tables", 32), ("tables", 12), ("more tables", 45)]
+        .into_iter()
+        .enumerate()
+    {
+        table
+            .insert(Person {
+                id: id as u64,
+                name: name.into(),
+                age,
+            })
+            .unwrap();
+    }
+
+    let snapshot = table.snapshot();
+    table.remove(&0);
+    let ranked: Vec<u64> = text
+        .search(&snapshot, &TextQuery::any("tables"))
+        .unwrap()
+        .into_iter()
+        .map(|(person, _)| person.id)
+        .collect();
+    assert_eq!(ranked, vec![0, 2]);
+    assert_eq!(
+        text.search(&table, &TextQuery::any("tables"))
+            .unwrap()
+            .len(),
+        1
+    );
+
+    // the handle only finds an index of the type it was created for
+    table.index_remove("text");
+    table
+        .index_add(
+            "text",
+            TextIndex::new(|item: &Person| vec![item.name.clone()]),
+        )
+        .unwrap();
+    assert!(matches!(
+        text.search(&table, &TextQuery::any("tables")),
+        Err(TableError::UnknownIndex(name)) if name == "text"
+    ));
+}
+
+#[test]
+fn can_index_multiple_keys() {
+    let mut table = Table::new();
+    let words =
+        |item: &Person| -> Vec<String> { item.name.split_whitespace().map(String::from).collect() };
+    let by_word = table
+        .index_add("word", MultiBTreeIndex::new(words))
+        .unwrap();
+    let by_alias = table
+        .index_add("alias", UniqueMultiBTreeIndex::new(words))
+        .unwrap();
+    for (id, name) in [(0, "Mike Mikey Mike"), (1, "John"), (2, "")] {
+        table
+            .insert(Person {
+                id,
+                name: name.into(),
+                age: 32,
+            })
+            .unwrap();
+    }
+
+    let ids = |rows: Box<dyn Iterator<Item = &Person> + '_>| -> Vec<u64> {
+        rows.map(|person| person.id).collect()
+    };
+    assert_eq!(
+        ids(by_word.lookup(&table, &"Mike".into()).unwrap()),
+        vec![0]
+    );
+    assert_eq!(
+        ids(by_alias.lookup(&table, &"Mikey".into()).unwrap()),
+        vec![0]
+    );
+    assert_eq!(
+        ids(Box::new(by_word.range(&table, "J".to_string()..).unwrap())),
+        vec![1, 0, 0]
+    );
+    assert_eq!(
+        table.index("word").unwrap().stats(),
+        Some(IndexStats {
+            keys: 3,
+            entries: 3
+        })
+    );
+
+    let result = table.insert(Person {
+        id: 3,
+        name: "Johnny John".into(),
+        age: 32,
+    });
+    assert!(matches!(result, Err(TableError::Duplicate(name, 1)) if name == "alias"));
+    assert!(ids(by_word.lookup(&table, &"Johnny".into()).unwrap()).is_empty());
+
+    table
+        .update(&0, |person| person.name = "Mikey".into())
+        .unwrap();
+    assert!(ids(by_word.lookup(&table, &"Mike".into()).unwrap()).is_empty());
+    assert!(ids(by_alias.lookup(&table, &"Mike".into()).unwrap()).is_empty());
+    assert_eq!(
+        ids(by_alias.lookup(&table, &"Mikey".into()).unwrap()),
+        vec![0]
+    );
+    table.remove(&0);
+    assert_eq!(
+        table.index("alias").unwrap().stats(),
+        Some(IndexStats {
+            keys: 1,
+            entries: 1
+        })
+    );
+}
+
+#[test]
+fn partial_index_only_contains_matching_rows() {
+    let mut table = Table::new();
+
+    // names are unique among adults only.
+    let by_name = table
+        .index_add(
+            "name",
+            PartialIndex::new(
+                UniqueBTreeIndex::new(|item: &Person| item.name.clone()),
+                |item: &Person| item.age >= 18,
+            ),
+        )
+        .unwrap();
+    for (id, age) in [(0, 10), (1, 40), (2, 12)] {
+        table
+            .insert(Person {
+                id,
+                name: "Mike".into(),
+                age,
+            })
+            .unwrap();
+    }
+    let ids = |table: &dyn View<Person>| -> Vec<u64> {
+        by_name
+            .lookup(&table, &"Mike".into())
+            .unwrap()
+            .map(|person| person.id)
+            .collect()
+    };
+    assert_eq!(ids(&table), vec![1]);
+
+    let result = table.update(&0, |person| person.age = 18);
+    assert!(matches!(result, Err(TableError::Duplicate(name, 1)) if name == "name"));
+    table.update(&1, |person| person.age = 17).unwrap();
+    table.update(&0, |person| person.age = 18).unwrap();
+    assert_eq!(ids(&table), vec![0]);
+
+    let snapshot = table.snapshot();
+    table.remove(&0);
+    assert!(ids(&table).is_empty());
+    assert_eq!(ids(&snapshot), vec![0]);
+    assert_eq!(table.query().eq(&by_name, "Mike").run().unwrap().count(), 0);
+}
+
+#[cfg(feature = "rtree")]
+#[test]
+fn can_query_rtree_index() {
+    let mut table = Table::new();
+    let location = table
+        .index_add(
+            "location",
+            RTreeIndex::new(|item: &Person| BoundingBox::point([item.id as f64, item.age as f64])),
+        )
+        .unwrap();
+    for (id, age) in [(0, 10), (1, 12), (5, 15), (9, 40), (20, 20)] {
+        table
+            .insert(Person {
+                id,
+                name: "Person".into(),
+                age,
+            })
+            .unwrap();
+    }
+
+    let ids = |table: &Table<Person>, query: SpatialQuery<2>| -> Vec<u64> {
+        location
+            .lookup(table, &query)
+            .unwrap()
+            .map(|person| person.id)
+            .collect()
+    };
+    let area = BoundingBox::new([6.0, 11.0], [0.0, 20.0]);
+    let mut inside = ids(&table, SpatialQuery::Intersects(area));
+    inside.sort();
+    assert_eq!(inside, vec![1, 5]);
+    assert_eq!(
+        ids(&table, SpatialQuery::Nearest([0.0, 0.0], 3)),
+        vec![0, 1, 5]
+    );
+    assert_eq!(
+        ids(&table, SpatialQuery::Nearest([19.0, 21.0], 1)),
+        vec![20]
+    );
+
+    // moved rows are found at their new location
+    table.update(&20, |person| person.age = 11).unwrap();
+    table.remove(&1);
+    let mut inside = ids(&table, SpatialQuery::Intersects(area));
+    inside.sort();
+    assert_eq!(inside, vec![5]);
+    let area = BoundingBox::new([15.0, 5.0], [25.0, 12.0]);
+    assert_eq!(ids(&table, SpatialQuery::Intersects(area)), vec![20]);
+}
+
+#[cfg(feature = "rtree")]
+#[test]
+fn rtree_index_rejects_coordinates_that_are_not_finite() {
+    let mut table = Table::new();
+    let location = table
+        .index_add(
+            "location",
+            RTreeIndex::new(|item: &Person| match item.name.as_str() {
+                "Nowhere" => BoundingBox::point([f64::NAN, 0.0]),
+                "Far away" => BoundingBox::new([0.0, 0.0], [f64::INFINITY, 1.0]),
+                _ => BoundingBox::point([item.id as f64, item.age as f64]),
+            }),
+        )
+        .unwrap();
+    let person = |id, name: &str| Person {
+        id,
+        name: name.into(),
+        age: 32,
+    };
+    table.insert(person(0, "Here")).unwrap();
+
+    for name in ["Nowhere", "Far away"] {
+        assert!(matches!(
+            table.insert(person(1, name)),
+            Err(TableError::InvalidKey(index)) if index == "location"
+        ));
+    }
+    assert!(matches!(
+        table.insert_many(vec![person(1, "There"), person(2, "Nowhere")]),
+        Err(TableError::InvalidKey(_))
+    ));
+    assert!(matches!(
+        table.update(&0, |person| person.name = "Nowhere".into()),
+        Err(TableError::InvalidKey(_))
+    ));
+    assert_eq!(table.len(), 1);
+    assert_eq!(table.lookup(&0).unwrap().name, "Here");
+
+    let nearest = |point| location.lookup(&table, &SpatialQuery::Nearest(point, 1));
+    assert_eq!(nearest([0.0, 0.0]).unwrap().count(), 1);
+    assert_eq!(nearest([f64::NAN, 0.0]).unwrap().count(), 0);
+}
+
+#[test]
+fn can_lookup_prefix_index() {
+    let mut table = Table::new();
+    let name = table
+        .index_add("name", PrefixIndex::new(|item: &Person| item.name.clone()))
+        .unwrap();
+    for (id, name) in ["Alice", "Albert", "Al", "Bob", "Alice", "Alfred", "Bea"]
+        .into_iter()
+        .enumerate()
+    {
+        table
+            .insert(Person {
+                id: id as u64,
+                name: name.into(),
+                age: 32,
+            })
+            .unwrap();
+    }
+
+    let ids = |table: &Table<Person>, prefix: &str, limit: usize| -> Vec<u64> {
+        name.lookup(table, &prefix.to_string())
+            .unwrap()
+            .take(limit)
+            .map(|person| person.id)
+            .collect()
+    };
+    assert_eq!(ids(&table, "Al", 10), vec![2, 1, 5, 0, 4]);
+    assert_eq!(ids(&table, "Al", 3), vec![2, 1, 5]);
+    assert_eq!(ids(&table, "Alice", 10), vec![0, 4]);
+    assert_eq!(ids(&table, "", 10), vec![2, 1, 5, 0, 4, 6, 3]);
+    assert!(ids(&table, "Alx", 10).is_empty());
+    assert_eq!(name.count(&table, &"Al".into()).unwrap(), 5);
+    assert_eq!(name.count(&table, &"B".into()).unwrap(), 2);
+    assert_eq!(name.count(&table, &"C".into()).unwrap(), 0);
+    assert_eq!(
+        table.index("name").unwrap().stats(),
+        Some(IndexStats {
+            keys: 6,
+            entries: 7
+        })
+    );
+
+    // renamed and removed rows leave the index
+    let snapshot = table.snapshot();
+    table
+        .update(&4, |person| person.name = "Bert".into())
+        .unwrap();
+    table.remove(&2);
+    assert_eq!(ids(&table, "Al", 10), vec![1, 5, 0]);
+    assert_eq!(ids(&table, "B", 10), vec![6, 4, 3]);
+    assert_eq!(name.count(&table, &"Al".into()).unwrap(), 3);
+    assert_eq!(name.count(&snapshot, &"Al".into()).unwrap(), 5);
+}
+
+#[test]
+fn can_subscribe_to_changes() {
+    let mut table = Table::new();
+    let changes = table.subscribe();
+    table
+        .insert(Person {
+            id: 0,
+            name: "Mike".into(),
+            age: 32,
+        })
+        .unwrap();
+    table.update(&0, |person| person.age = 33).unwrap();
+    table.remove(&0);
+    table.clear();
+
+    let changes: Vec<Change<Person>> = changes.try_iter().collect();
+    assert_eq!(changes.len(), 4);
+    assert!(matches!(changes[0], Change::Inserted(0)));
+    assert!(matches!(&changes[1], Change::Updated(old, new) if old.age == 32 && new.age == 33));
+    assert!(matches!(&changes[2], Change::Removed(person) if person.age == 33));
+    assert!(matches!(changes[3], Change::Cleared));
+
+    // dropped subscriptions are ignored
+    drop(table.subscribe());
+    table.clear();
+}
+
+#[test]
+fn can_watch_changes() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut table = Table::new();
+    let events = log.clone();
+    table.watch_add("log", move |change: &Change<Person>| {
+        events.borrow_mut().push(match change {
+            Change::Inserted(key) => format!("inserted {key}"),
+            Change::Updated(old, new) => format!("updated {} to {}", old.name, new.name),
+            Change::Removed(person) => format!("removed {}", person.id),
+            Change::Cleared => "cleared".into(),
+        });
+    });
+    table
+        .insert(Person {
+            id: 0,
+            name: "Mike".into(),
+            age: 32,
+        })
+        .unwrap();
+    table
+        .upsert(Person {
+            id: 0,
+            name: "Michael".into(),
+            age: 32,
+        })
+        .unwrap();
+    table.watch_remove("log");
+    table.remove(&0);
+
+    assert_eq!(
+        *log.borrow(),
+        vec!["inserted 0".to_string(), "updated Mike to Michael".into()]
+    );
+}
+
+#[test]
+fn rolled_back_changes_are_undone_for_subscribers() {
+    let mut table = Table::new();
+    table
+        .insert(Person {
+            id: 0,
+            name: "Mike".into(),
+            age: 32,
+        })
+        .unwrap();
+    let changes = table.subscribe();
+
+    let result: Result<(), TableError<Person>> = table.transaction(|table| {
+        table.update(&0, |person| person.age = 33)?;
+        table.clear();
+        table.insert(Person {
+            id: 1,
+            name: "John".into(),
+            age: 18,
+        })?;
+        table.insert(Person {
+            id: 1,
+            name: "John".into(),
+            age: 18,
+        })?;
+        Ok(())
+    });
+    assert!(matches!(result, Err(TableError::Exists(1))));
+
+    let changes: Vec<Change<Person>> = changes.try_iter().collect();
+    assert_eq!(changes.len(), 6);
+    assert!(matches!(&changes[0], Change::Updated(_, new) if new.age == 33));
+    assert!(matches!(changes[1], Change::Cleared));
+    assert!(matches!(changes[2], Change::Inserted(1)));
+    assert!(matches!(&changes[3], Change::Removed(person) if person.id == 1));
+    assert!(matches!(changes[4], Change::Inserted(0)));
+    assert!(matches!(&changes[5], Change::Updated(old, new) if old.age == 33 && new.age == 32));
+}
+
+#[test]
+fn can_share_table_between_threads() {
+    let mut table: Table<Person, Shared> = Table::default();
+    let by_age = table
+        .index_add("age", BTreeIndex::new(|item: &Person| item.age))
+        .unwrap();
+    table
+        .constraint_add("adult", |person: &Person| match person.age {
+            0..=17 => Err(Box::new(MyError::Fail).into()),
+            _ => Ok(()),
+        })
+        .unwrap();
+    let table = ConcurrentTable::new(table);
+
+    let writers: Vec<_> = (0..4)
+        .map(|thread| {
+            let table = table.clone();
+            std::thread::spawn(move || {
+                for id in 0..100 {
+                    table
+                        .write()
+                        .insert(Person {
+                            id: thread * 100 + id,
+                            name: format!("Person {id}"),
+                            age: 18 + (id % 50) as u16,
+                        })
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    let readers: Vec<_> = (0..4)
+        .map(|_| {
+            let table = table.clone();
+            let by_age = by_age.clone();
+            std::thread::spawn(move || {
+                for _ in 0..100 {
+                    let table = table.read();
+                    let count = by_age.range(&table, 18..).unwrap().count();
+                    assert_eq!(count, table.len());
+                }
+            })
+        })
+        .collect();
+    for thread in writers.into_iter().chain(readers) {
+        thread.join().unwrap();
+    }
+
+    assert_eq!(table.read().len(), 400);
+    assert_eq!(table.lookup(&399).unwrap().age, 67);
+    let result = table.transaction(|table| {
+        table.insert(Person {
+            id: 400,
+            name: "Child".into(),
+            age: 10,
+        })
+    });
+    assert!(matches!(result, Err(TableError::Constraint(name, _)) if name == "adult"));
+    assert_eq!(table.read().len(), 400);
+}
+
+#[test]
+fn foreign_keys_keep_tables_consistent() {
+    #[derive(Debug, Clone)]
+    struct Order {
+        id: u64,
+        customer: Option<u64>,
+    }
+
+    impl Identity for Order {
+        type PrimaryKey = u64;
+        fn primary_key(&self) -> Self::PrimaryKey {
+            self.id
+        }
+    }
+
+    let customers = ConcurrentTable::default();
+    for id in 0..3 {
+        customers
+            .write()
+            .insert(Person {
+                id,
+                name: format!("Person {id}"),
+                age: 30,
+            })
+            .unwrap();
+    }
+    let order = |id, customer| Order { id, customer };
+
+    // each foreign key gets its own table, so they can be told apart.
+    let restricted = ConcurrentTable::default();
+    let cascaded = ConcurrentTable::default();
+    let nulled = ConcurrentTable::default();
+    restricted.write().insert(order(0, Some(5))).unwrap();
+    let result = restricted.foreign_key_add(
+        "customer",
+        &customers,
+        |order: &Order| order.customer,
+        OnRemove::Restrict,
+    );
+    assert!(matches!(result, Err(TableError::ForeignKey(name)) if name == "customer"));
+    restricted.write().clear();
+    for (table, name, on_remove) in [
+        (&restricted, "restricted", OnRemove::Restrict),
+        (&cascaded, "cascaded", OnRemove::Cascade),
+        (
+            &nulled,
+            "nulled",
+            OnRemove::SetNull(|order: &mut Order| order.customer = None),
+        ),
+    ] {
+        table
+            .foreign_key_add(name, &customers, |order: &Order| order.customer, on_remove)
+            .unwrap();
+    }
+
+    let result = cascaded.write().insert(order(0, Some(5)));
+    assert!(matches!(result, Err(TableError::ForeignKey(name)) if name == "cascaded"));
+    restricted.write().insert(order(0, Some(0))).unwrap();
+    restricted.write().insert(order(1, None)).unwrap();
+    cascaded.write().insert(order(0, Some(1))).unwrap();
+    cascaded.write().insert(order(1, Some(2))).unwrap();
+    nulled.write().insert(order(0, Some(1))).unwrap();
+    let result = nulled.write().update(&0, |order| order.customer = Some(7));
+    assert!(matches!(result, Err(TableError::ForeignKey(name)) if name == "nulled"));
+
+    let result = customers.write().try_remove(&0);
+    assert!(matches!(result, Err(TableError::Referenced(name, 0)) if name == "restricted"));
+    assert_eq!(customers.read().len(), 3);
+
+    // changes to other tables could not be undone along with a transaction.
+    let result = customers.transaction(|customers| customers.try_remove(&1));
+    assert!(matches!(result, Err(TableError::Referenced(_, 1))));
+    assert_eq!(cascaded.read().len(), 2);
+    assert_eq!(nulled.read().lookup(&0).unwrap().customer, Some(1));
+    customers
+        .transaction(|customers| customers.try_remove(&2))
+        .unwrap_err();
+    customers
+        .write()
+        .insert(Person {
+            id: 3,
+            name: "Person 3".into(),
+            age: 30,
+        })
+        .unwrap();
+    customers
+        .transaction(|customers| customers.try_remove(&3))
+        .unwrap();
+
+    customers.write().try_remove(&1).unwrap().unwrap();
+    assert_eq!(cascaded.read().keys().copied().collect::<Vec<_>>(), vec![1]);
+    assert_eq!(nulled.read().lookup(&0).unwrap().customer, None);
+
+    // nothing is removed or updated when any foreign key restricts removal.
+    let result = customers.write().try_retain(|person| person.id == 1);
+    assert!(matches!(result, Err(TableError::Referenced(name, 0)) if name == "restricted"));
+    assert_eq!(customers.read().len(), 2);
+    let result = customers.write().try_clear();
+    assert!(matches!(result, Err(TableError::Referenced(name, 0)) if name == "restricted"));
+    assert_eq!(cascaded.read().len(), 1);
+    restricted.foreign_key_remove("restricted", &customers);
+    customers.write().clear();
+    assert!(cascaded.read().is_empty());
+    restricted.write().insert(order(2, Some(9))).unwrap();
+}
+
+#[test]
+fn foreign_keys_are_told_apart_by_table() {
+    let people: ConcurrentTable<Person> = ConcurrentTable::default();
+    let friends: ConcurrentTable<Person> = ConcurrentTable::default();
+    let pets: ConcurrentTable<Person> = ConcurrentTable::default();
+    let person = |id, age| Person {
+        id,
+        name: format!("Person {id}"),
+        age,
+    };
+    for id in 0..2 {
+        people.write().insert(person(id, 30)).unwrap();
+    }
+    let reference = |item: &Person| Some(item.age as u64);
+
+    // the same name can be used by different tables referencing the same one.
+    friends
+        .foreign_key_add("person", &people, reference, OnRemove::Restrict)
+        .unwrap();
+    pets.foreign_key_add("person", &people, reference, OnRemove::Cascade)
+        .unwrap();
+    friends.write().insert(person(0, 0)).unwrap();
+    pets.write().insert(person(0, 1)).unwrap();
+    let result = people.write().try_remove(&0);
+    assert!(matches!(result, Err(TableError::Referenced(name, 0)) if name == "person"));
+    people.write().try_remove(&1).unwrap();
+    assert!(pets.read().is_empty());
+
+    let result = pets.foreign_key_add("person", &friends, reference, OnRemove::Restrict);
+    assert!(matches!(result, Err(TableError::ForeignKeyExists(name)) if name == "person"));
+    let result = people.foreign_key_add("self", &people, reference, OnRemove::Restrict);
+    assert!(matches!(result, Err(TableError::ForeignKeyCycle(name)) if name == "self"));
+    let result = people.foreign_key_add("pet", &pets, reference, OnRemove::Restrict);
+    assert!(matches!(result, Err(TableError::ForeignKeyCycle(name)) if name == "pet"));
+
+    // only the foreign key referencing the given table is removed.
+    pets.foreign_key_remove("person", &friends);
+    let result = pets.write().insert(person(1, 7));
+    assert!(matches!(result, Err(TableError::ForeignKey(name)) if name == "person"));
+    let result = people.foreign_key_add("pet", &pets, reference, OnRemove::Restrict);
+    assert!(matches!(result, Err(TableError::ForeignKeyCycle(name)) if name == "pet"));
+
+    pets.foreign_key_remove("person", &people);
+    people
+        .foreign_key_add("pet", &pets, |_: &Person| None, OnRemove::Restrict)
+        .unwrap();
+}
+
+#[test]
+fn foreign_keys_index_references_without_locking_parent() {
+    let people: ConcurrentTable<Person> = ConcurrentTable::default();
+    let pets: ConcurrentTable<Person> = ConcurrentTable::default();
+    let person = |id, age| Person {
+        id,
+        name: format!("Person {id}"),
+        age,
+    };
+    for id in 0..2 {
+        people.write().insert(person(id, 30)).unwrap();
+    }
+    let reference = |item: &Person| Some(item.age as u64);
+    pets.write()
+        .index_add("age", BTreeIndex::new(|item: &Person| item.age))
+        .unwrap();
+    let result = pets.foreign_key_add("age", &people, reference, OnRemove::Cascade);
+    assert!(matches!(result, Err(TableError::IndexExists(name)) if name == "age"));
+    pets.foreign_key_add("owner", &people, reference, OnRemove::Cascade)
+        .unwrap();
+    pets.write().insert(person(0, 1)).unwrap();
+    let owned = pets.read().index_lookup("owner", &1u64).unwrap().count();
+    assert_eq!(owned, 1);
+
+    // adding pets while their owners are removed neither deadlocks nor leaves pets behind.
+    let adding = {
+        let pets = pets.clone();
+        std::thread::spawn(move || {
+            for id in 1..500 {
+                let _ = pets.write().insert(person(id, (id % 2) as u16));
+            }
+        })
+    };
+    for _ in 0..100 {
+        for id in 0..2 {
+            people.write().try_remove(&id).unwrap();
+            people.write().insert(person(id, 30)).unwrap();
+        }
+    }
+    adding.join().unwrap();
+    let owners = people.read();
+    assert!(pets
+        .read()
+        .iter()
+        .all(|pet| owners.lookup(&(pet.age as u64)).is_some()));
+    drop(owners);
+
+    pets.foreign_key_remove("owner", &people);
+    assert!(pets.read().index("owner").is_err());
+    assert!(pets.read().index("age").is_ok());
+}
+
+#[test]
+fn foreign_keys_undo_changes_when_a_later_one_refuses() {
+    #[derive(Debug, Clone)]
+    struct Pet {
+        id: u64,
+        owner: Option<u64>,
+        vet: Option<u64>,
+    }
+
+    impl Identity for Pet {
+        type PrimaryKey = u64;
+        fn primary_key(&self) -> Self::PrimaryKey {
+            self.id
+        }
+    }
+
+    let people = ConcurrentTable::default();
+    for id in 0..2 {
+        people
+            .write()
+            .insert(Person {
+                id,
+                name: format!("Person {id}"),
+                age: 30,
+            })
+            .unwrap();
+    }
+    let pets = ConcurrentTable::default();
+    let toys: ConcurrentTable<Person> = ConcurrentTable::default();
+    pets.write()
+        .constraint_add("vet", |pet: &Pet| match pet.vet {
+            Some(_) => Ok(()),
+            None => Err(anyhow!("pets need a vet").into()),
+        })
+        .unwrap();
+
+    // foreign keys of the same table act in order of their names.
+    pets.foreign_key_add("a_owner", &people, |pet: &Pet| pet.owner, OnRemove::Cascade)
+        .unwrap();
+    pets.foreign_key_add(
+        "b_vet",
+        &people,
+        |pet: &Pet| pet.vet,
+        OnRemove::SetNull(|pet: &mut Pet| pet.vet = None),
+    )
+    .unwrap();
+    toys.foreign_key_add(
+        "pet",
+        &pets,
+        |toy: &Person| Some(toy.age as u64),
+        OnRemove::Cascade,
+    )
+    .unwrap();
+    let pet = |id, owner, vet| Pet {
+        id,
+        owner: Some(owner),
+        vet: Some(vet),
+    };
+    pets.write().insert(pet(0, 1, 0)).unwrap();
+    pets.write().insert(pet(1, 0, 1)).unwrap();
+    toys.write()
+        .insert(Person {
+            id: 0,
+            name: "Ball".into(),
+            age: 0,
+        })
+        .unwrap();
+
+    let result = people.write().try_remove(&1);
+    assert!(matches!(result, Err(TableError::Referenced(name, 1)) if name == "b_vet"));
+    assert_eq!(people.read().len(), 2);
+    assert_eq!(pets.read().len(), 2);
+    assert_eq!(toys.read().len(), 1);
+    pets.write().insert(pet(2, 1, 1)).unwrap();
+
+    pets.write().update(&1, |pet| pet.vet = Some(0)).unwrap();
+    pets.write().update(&2, |pet| pet.vet = Some(0)).unwrap();
+    people.write().try_remove(&1).unwrap();
+    assert_eq!(pets.read().keys().copied().collect::<Vec<_>>(), vec![1]);
+    assert!(toys.read().is_empty());
+}
+
+#[test]
+fn foreign_keys_apply_when_primary_key_changes() {
+    let people: ConcurrentTable<Person> = ConcurrentTable::default();
+    let friends: ConcurrentTable<Person> = ConcurrentTable::default();
+    let pets: ConcurrentTable<Person> = ConcurrentTable::default();
+    let person = |id, age| Person {
+        id,
+        name: format!("Person {id}"),
+        age,
+    };
+    for id in 0..3 {
+        people.write().insert(person(id, 30)).unwrap();
+    }
+    let reference = |item: &Person| Some(item.age as u64);
+    friends
+        .foreign_key_add("friend", &people, reference, OnRemove::Restrict)
+        .unwrap();
+    pets.foreign_key_add("pet", &people, reference, OnRemove::Cascade)
+        .unwrap();
+    friends.write().insert(person(0, 0)).unwrap();
+    pets.write().insert(person(0, 1)).unwrap();
+
+    let result = people.write().update(&0, |person| person.id = 10);
+    assert!(matches!(result, Err(TableError::Referenced(name, 0)) if name == "friend"));
+    assert!(people.read().lookup(&0).is_some());
+    assert!(people.read().lookup(&10).is_none());
+    people.write().update(&0, |person| person.age = 40).unwrap();
+
+    people.write().update(&1, |person| person.id = 11).unwrap();
+    assert!(pets.read().is_empty());
+    assert!(people.read().lookup(&11).is_some());
+
+    // elements missing from restored data are removed as well.
+    #[cfg(feature = "serde")]
+    {
+        let snapshot = r#"[{"id":1,"name":"Mike","age":32}]"#;
+        let result = people
+            .write()
+            .restore(&mut serde_json::Deserializer::from_str(snapshot));
+        assert!(result.is_err());
+        assert_eq!(people.read().len(), 3);
+    }
+}
+
+#[test]
+fn snapshot_is_unaffected_by_later_changes() {
+    let mut table: Table<Person, Shared> = Table::default();
+    let by_age = table
+        .index_add("age", BTreeIndex::new(|item: &Person| item.age))
+        .unwrap();
+    let by_name = table
+        .index_add(
+            "name",
+            UniqueBTreeIndex::new(|item: &Person| item.name.clone()),
+        )
+        .unwrap();
+    for (id, name) in ["Mike", "John", "Jane"].into_iter().enumerate() {
+        table
+            .insert(Person {
+                id: id as u64,
+                name: name.into(),
+                age: 32,
+            })
+            .unwrap();
+    }
+
+    let snapshot = table.snapshot();
+    table.update(&0, |person| person.age = 45).unwrap();
+    let removed = table.remove(&1).unwrap();
+    assert_eq!(removed.name, "John");
+    table
+        .insert(Person {
+            id: 3,
+            name: "Mary".into(),
+            age: 18,
+        })
+        .unwrap();
+    table.clear();
+    assert!(table.is_empty());
+
+    // the snapshot of a shared table can be read from another thread, without holding on to
+    // the table.
+    let reader = std::thread::spawn(move || {
+        assert_eq!(snapshot.len(), 3);
+        assert_eq!(snapshot.lookup(&0).unwrap().age, 32);
+        assert_eq!(snapshot.lookup(&1).unwrap().name, "John");
+        assert!(snapshot.lookup(&3).is_none());
+        assert_eq!(by_age.lookup(&snapshot, &32).unwrap().count(), 3);
+        assert_eq!(by_age.range(&snapshot, 40..).unwrap().count(), 0);
+        let names: Vec<String> = by_name
+            .range(&snapshot, ..)
+            .unwrap()
+            .map(|person| person.name.clone())
+            .collect();
+        assert_eq!(names, vec!["Jane", "John", "Mike"]);
+    });
+    reader.join().unwrap();
+}
+
+#[test]
+fn can_lookup_hash_index_in_snapshot() {
+    let mut table = Table::new();
+    let by_name = table
+        .index_add(
+            "name",
+            UniqueHashIndex::new(|item: &Person| item.name.clone()),
+        )
+        .unwrap();
+    table
+        .insert(Person {
+            id: 0,
+            name: "Mike".into(),
+            age: 32,
+        })
+        .unwrap();
+    let snapshot = table.snapshot();
+    table
+        .update(&0, |person| person.name = "Michael".into())
+        .unwrap();
+
+    let ids: Vec<u64> = by_name
+        .lookup(&snapshot, &"Mike".into())
+        .unwrap()
+        .map(|person| person.id)
+        .collect();
+    assert_eq!(ids, vec![0]);
+    assert_eq!(by_name.lookup(&table, &"Mike".into()).unwrap().count(), 0);
+    assert!(matches!(
+        snapshot.index_lookup("age", &32u16),
+        Err(TableError::UnknownIndex(name)) if name == "age"
+    ));
+}
+
+#[test]
+fn local_table_can_have_indices_that_stay_on_one_thread() {
+    let mut table = Table::new();
+    let decades = std::rc::Rc::new(10);
+    let by_decade = table
+        .index_add(
+            "decade",
+            BTreeIndex::new({
+                let decades = decades.clone();
+                move |item: &Person| item.age / *decades
+            }),
+        )
+        .unwrap();
+    for (id, age) in [32, 45, 38].into_iter().enumerate() {
+        table
+            .insert(Person {
+                id: id as u64,
+                name: format!("Person {id}"),
+                age,
+            })
+            .unwrap();
+    }
+
+    let snapshot = table.snapshot();
+    table.remove(&0);
+    assert_eq!(by_decade.lookup(&table, &3).unwrap().count(), 1);
+    assert_eq!(by_decade.lookup(&snapshot, &3).unwrap().count(), 2);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn can_save_and_load_table() {
+    let mut table = Table::new();
+    for (id, name) in ["Mike", "John"].into_iter().enumerate() {
+        table
+            .insert(Person {
+                id: id as u64,
+                name: name.into(),
+                age: 32,
+            })
+            .unwrap();
+    }
+    let snapshot = serde_json::to_string(&table).unwrap();
+
+    let mut table: Table<Person> = serde_json::from_str(&snapshot).unwrap();
+    let by_name = table
+        .index_add(
+            "name",
+            UniqueBTreeIndex::new(|item: &Person| item.name.clone()),
+        )
+        .unwrap();
+    assert_eq!(table.len(), 2);
+    let ids: Vec<u64> = by_name
+        .lookup(&table, &"John".into())
+        .unwrap()
+        .map(|person| person.id)
+        .collect();
+    assert_eq!(ids, vec![1]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn can_restore_into_configured_table() {
+    let mut table = Table::new();
+    table.pre_insert_hook_add("primary_key", |table: &mut Table<Person>, item| {
+        item.id = table.len() as u64;
+    });
+    let by_name = table
+        .index_add(
+            "name",
+            UniqueBTreeIndex::new(|item: &Person| item.name.clone()),
+        )
+        .unwrap();
+    table
+        .insert(Person {
+            id: 0,
+            name: "Jane".into(),
+            age: 32,
+        })
+        .unwrap();
+
+    let snapshot = r#"[{"id":4,"name":"Mike","age":32},{"id":7,"name":"John","age":24}]"#;
+    table
+        .restore(&mut serde_json::Deserializer::from_str(snapshot))
+        .unwrap();
+    assert_eq!(table.len(), 2);
+    assert!(table.lookup(&7).is_some());
+    assert_eq!(by_name.lookup(&table, &"Jane".into()).unwrap().count(), 0);
+    assert_eq!(by_name.lookup(&table, &"Mike".into()).unwrap().count(), 1);
+
+    // duplicate names are rejected, leaving the table unchanged
+    let snapshot = r#"[{"id":1,"name":"Mary","age":32},{"id":2,"name":"Mary","age":24}]"#;
+    let result = table.restore(&mut serde_json::Deserializer::from_str(snapshot));
+    assert!(result.is_err());
+    assert_eq!(table.len(), 2);
+    assert_eq!(by_name.lookup(&table, &"Mike".into()).unwrap().count(), 1);
+    assert_eq!(by_name.lookup(&table, &"Mary".into()).unwrap().count(), 0);
+}
+
+#[cfg(feature = "wal")]
+fn wal_directory(name: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("table-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    path
+}
+
+#[cfg(feature = "wal")]
+fn wal_table() -> (Table<Person>, IndexHandle<Person, String>) {
+    let mut table = Table::new();
+    let by_name = table
+        .index_add(
+            "name",
+            UniqueBTreeIndex::new(|item: &Person| item.name.clone()),
+        )
+        .unwrap();
+    (table, by_name.into())
+}
+
+#[cfg(feature = "wal")]
+#[test]
+fn can_reopen_durable_table() {
+    let path = wal_directory("reopen");
+
+    let (table, _) = wal_table();
+    let mut durable = DurableTable::open(&path, table).unwrap();
+    for (id, name) in ["Mike", "John", "Jane"].into_iter().enumerate() {
+        durable
+            .insert(Person {
+                id: id as u64,
+                name: name.into(),
+                age: 32,
+            })
+            .unwrap();
+    }
+    durable.update(&0, |person| person.age = 33).unwrap();
+    durable.remove(&1).unwrap();
+    let result = durable.insert(Person {
+        id: 3,
+        name: "Jane".into(),
+        age: 32,
+    });
+    assert!(matches!(
+        result,
+        Err(WalError::Table(TableError::Duplicate(_, 2)))
+    ));
+    drop(durable);
+
+    let (table, by_name) = wal_table();
+    let durable = DurableTable::open(&path, table).unwrap();
+    assert_eq!(durable.len(), 2);
+    assert_eq!(durable.lookup(&0).unwrap().age, 33);
+    assert!(durable.lookup(&1).is_none());
+    assert!(durable.lookup(&3).is_none());
+    assert_eq!(by_name.lookup(&durable, &"Jane".into()).unwrap().count(), 1);
+
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[cfg(feature = "wal")]
+#[test]
+fn can_compact_durable_table() {
+    let path = wal_directory("compact");
+
+    let (table, _) = wal_table();
+    let mut durable = DurableTable::open(&path, table).unwrap();
+    durable
+        .transaction(|table| {
+            table.insert(Person {
+                id: 0,
+                name: "Mike".into(),
+                age: 32,
+            })?;
+            table.insert(Person {
+                id: 1,
+                name: "John".into(),
+                age: 32,
+            })?;
+            Ok(())
+        })
+        .unwrap();
+    durable.compact().unwrap();
+    assert_eq!(std::fs::metadata(path.join("log")).unwrap().len(), 0);
+    durable.clear().unwrap();
+    durable
+        .insert(Person {
+            id: 2,
+            name: "Jane".into(),
+            age: 32,
+        })
+        .unwrap();
+    drop(durable);
+
+    let (table, _) = wal_table();
+    let durable = DurableTable::open(&path, table).unwrap();
+    assert_eq!(durable.len(), 1);
+    assert!(durable.lookup(&2).is_some());
+
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[cfg(feature = "wal")]
+#[test]
+fn durable_table_survives_crash_during_compaction() {
+    let path = wal_directory("compact-crash");
+    let person = |id, name: &str| Person {
+        id,
+        name: name.into(),
+        age: 32,
+    };
+
+    let (table, _) = wal_table();
+    let mut durable = DurableTable::open(&path, table).unwrap();
+    durable.insert(person(1, "A")).unwrap();
+    durable
+        .update(&1, |person| person.name = "B".into())
+        .unwrap();
+    durable.insert(person(2, "A")).unwrap();
+
+    // simulate a crash after the new snapshot is in place, but before the log is truncated
+    let log = std::fs::read(path.join("log")).unwrap();
+    durable.compact().unwrap();
+    drop(durable);
+    std::fs::write(path.join("log"), log).unwrap();
+
+    let (table, by_name) = wal_table();
+    let mut durable = DurableTable::open(&path, table).unwrap();
+    assert_eq!(durable.len(), 2);
+    assert_eq!(durable.lookup(&1).unwrap().name, "B");
+    durable.insert(person(3, "C")).unwrap();
+    drop(durable);
+
+    let (table, _) = wal_table();
+    let durable = DurableTable::open(&path, table).unwrap();
+    assert_eq!(durable.len(), 3);
+    assert_eq!(
+        by_name
+            .lookup(&durable, &"A".into())
+            .unwrap()
+            .next()
+            .unwrap()
+            .id,
+        2
+    );
+
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[cfg(feature = "wal")]
+#[test]
+fn durable_table_ignores_torn_writes() {
+    use std::io::Write;
+
+    let path = wal_directory("torn");
+
+    let (table, _) = wal_table();
+    let mut durable = DurableTable::open(&path, table).unwrap();
+    durable
+        .insert(Person {
+            id: 0,
+            name: "Mike".into(),
+            age: 32,
+        })
+        .unwrap();
+    drop(durable);
+
+    // simulate a crash in the middle of writing a frame
+    let mut log = std::fs::OpenOptions::new()
+        .append(true)
+        .open(path.join("log"))
+        .unwrap();
+    log.write_all(&[42, 0, 0, 0, 1, 2]).unwrap();
+    drop(log);
+
+    let (table, _) = wal_table();
+    let mut durable = DurableTable::open(&path, table).unwrap();
+    assert_eq!(durable.len(), 1);
+    durable
+        .insert(Person {
+            id: 1,
+            name: "John".into(),
+            age: 32,
+        })
+        .unwrap();
+    drop(durable);
+
+    let (table, _) = wal_table();
+    let durable = DurableTable::open(&path, table).unwrap();
+    assert_eq!(durable.len(), 2);
+
+    std::fs::remove_dir_all(&path).unwrap();
+}
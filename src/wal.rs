@@ -0,0 +1,302 @@
+use crate::table::Changes;
+use crate::{Identity, Local, Table, TableError, Threading, WalError};
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// Name of the snapshot file in the directory of a durable table.
+const SNAPSHOT: &str = "snapshot";
+
+/// Name of the snapshot file while it is being written.
+const SNAPSHOT_TEMPORARY: &str = "snapshot.tmp";
+
+/// Name of the log file in the directory of a durable table.
+const LOG: &str = "log";
+
+/// Size of the frame header, containing the length and checksum of the payload.
+const HEADER: usize = 8;
+
+/// Frame of the log, the changes of one transaction along with the generation of the log.
+///
+/// Compacting the log starts a new generation, recorded in the snapshot as well, so frames of
+/// an older generation that are left over after a crash are skipped instead of replayed.
+type Frame<T, K> = (u64, Vec<Record<T, K>>);
+
+/// Change to a single element, as recorded in the log.
+///
+/// Records describe the state after the change rather than the operation, so replaying them
+/// more than once gives the same result.
+#[derive(Serialize, Deserialize)]
+enum Record<T, K> {
+    Put(T),
+    Delete(K),
+    Clear,
+}
+
+/// Append-only log file, made up of framed and checksummed batches of records.
+struct Log {
+    file: File,
+    length: u64,
+    /// Generation of the frames appended to the log, the one of the last snapshot.
+    generation: u64,
+}
+
+impl Log {
+    /// Append the changes of a transaction as one frame, and wait for it to be on disk.
+    fn append<T, S>(
+        &mut self,
+        table: &Table<T, S>,
+        changes: Changes<T::PrimaryKey>,
+    ) -> Result<(), WalError<T>>
+    where
+        T: Identity + Serialize,
+        T::PrimaryKey: Serialize,
+        S: Threading,
+    {
+        let mut records = Vec::new();
+        if changes.cleared {
+            records.push(Record::Clear);
+        }
+        for key in &changes.keys {
+            match table.lookup(key) {
+                Some(element) => records.push(Record::Put(element)),
+                None => records.push(Record::Delete(key)),
+            }
+        }
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        let payload = bincode::options().serialize(&(self.generation, records))?;
+        let length = u32::try_from(payload.len()).map_err(|_| {
+            std::io::Error::new(ErrorKind::InvalidInput, "transaction too large for the log")
+        })?;
+        let mut frame = Vec::with_capacity(HEADER + payload.len());
+        frame.extend_from_slice(&length.to_le_bytes());
+        frame.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+        frame.extend_from_slice(&payload);
+
+        // a partially written frame would hide all frames after it, so cut it off.
+        if let Err(error) = self.write(&frame) {
+            let _ = self.file.set_len(self.length);
+            return Err(error.into());
+        }
+        self.length += frame.len() as u64;
+
+        Ok(())
+    }
+
+    fn write(&mut self, frame: &[u8]) -> std::io::Result<()> {
+        self.file.write_all(frame)?;
+        self.file.sync_data()
+    }
+
+    /// Discard everything in the log after the given length.
+    fn truncate(&mut self, length: u64) -> std::io::Result<()> {
+        self.file.set_len(length)?;
+        self.file.sync_all()?;
+        self.length = length;
+        Ok(())
+    }
+}
+
+/// Parse the frame at the start of the bytes, returning its payload and total length.
+///
+/// Returns nothing if the frame is incomplete or its checksum does not match, which happens
+/// when the process died while writing it.
+fn frame_parse(bytes: &[u8]) -> Option<(&[u8], usize)> {
+    let header = bytes.get(..HEADER)?;
+    let length = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let checksum = u32::from_le_bytes(header[4..].try_into().unwrap());
+    let payload = bytes.get(HEADER..HEADER + length)?;
+    (crc32fast::hash(payload) == checksum).then_some((payload, HEADER + length))
+}
+
+/// Table that records every change in a write-ahead log, so it can be reopened later.
+///
+/// The state is kept in a directory, holding the last snapshot and a log of all changes made
+/// since. Every change is written to the log and synced to disk before it is committed. When
+/// the table is opened, the snapshot is loaded and the log replayed on top of it.
+/// [`DurableTable::compact`] rolls the log into a new snapshot.
+///
+/// Indices, constraints and hooks are code, so they are registered on the [`Table`] that is
+/// passed to [`DurableTable::open`]. Hooks are not applied when replaying the log, because the
+/// logged elements already went through them. Reading works through [`Deref`], while all
+/// changes go through the methods of the durable table.
+pub struct DurableTable<T: Identity, S: Threading = Local> {
+    table: Table<T, S>,
+    path: PathBuf,
+    log: Log,
+}
+
+impl<T, S> DurableTable<T, S>
+where
+    S: Threading,
+    T: Identity + Clone + Serialize + DeserializeOwned,
+    T::PrimaryKey: Serialize + DeserializeOwned,
+{
+    /// Open the durable table stored in the directory, creating it if it does not exist.
+    ///
+    /// Any elements already in `table` are replaced by the stored ones.
+    pub fn open(path: impl AsRef<Path>, mut table: Table<T, S>) -> Result<Self, WalError<T>> {
+        let path = path.as_ref().to_path_buf();
+        fs::create_dir_all(&path)?;
+
+        // load the last snapshot, which starts with its generation
+        let generation = match File::open(path.join(SNAPSHOT)) {
+            Ok(file) => {
+                let mut deserializer =
+                    bincode::Deserializer::with_reader(BufReader::new(file), bincode::options());
+                let generation = u64::deserialize(&mut deserializer)?;
+                table.restore(&mut deserializer)?;
+                generation
+            }
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                table.clear_unhooked()?;
+                0
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        // replay the log on top of it
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path.join(LOG))?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let mut length = 0;
+        while let Some((payload, size)) = frame_parse(&bytes[length..]) {
+            let (frame, records): Frame<T, T::PrimaryKey> =
+                bincode::options().deserialize(payload)?;
+
+            // frames of older generations are already in the snapshot.
+            if frame >= generation {
+                table.transaction(|table| {
+                    records
+                        .into_iter()
+                        .try_for_each(|record| replay(table, record))
+                })?;
+            }
+            length += size;
+        }
+
+        // drop a torn frame at the end, so new frames are not appended after it.
+        let mut log = Log {
+            file,
+            length: length as u64,
+            generation,
+        };
+        if length < bytes.len() {
+            log.truncate(length as u64)?;
+        }
+
+        Ok(DurableTable { table, path, log })
+    }
+
+    /// Run a closure as a transaction, logging all of its changes as a single record.
+    ///
+    /// If the closure fails or the changes cannot be logged, the transaction is rolled back.
+    pub fn transaction<R>(
+        &mut self,
+        transaction: impl FnOnce(&mut Table<T, S>) -> Result<R, TableError<T>>,
+    ) -> Result<R, WalError<T>> {
+        let log = &mut self.log;
+        self.table.transaction_commit(
+            |table| transaction(table).map_err(WalError::from),
+            |table, changes| log.append(table, changes),
+        )
+    }
+
+    /// Try inserting an element, see [`Table::insert`].
+    pub fn insert(&mut self, element: T) -> Result<T::PrimaryKey, WalError<T>> {
+        self.transaction(|table| table.insert(element))
+    }
+
+    /// Insert or replace an element, see [`Table::upsert`].
+    pub fn upsert(&mut self, element: T) -> Result<T::PrimaryKey, WalError<T>> {
+        self.transaction(|table| table.upsert(element))
+    }
+
+    /// Update an element in place, see [`Table::update`].
+    pub fn update(
+        &mut self,
+        key: &T::PrimaryKey,
+        update: impl FnOnce(&mut T),
+    ) -> Result<T::PrimaryKey, WalError<T>> {
+        self.transaction(|table| table.update(key, update))
+    }
+
+    /// Remove an element by its primary key, see [`Table::remove`].
+    pub fn remove(&mut self, key: &T::PrimaryKey) -> Result<Option<T>, WalError<T>> {
+        self.transaction(|table| Ok(table.remove(key)))
+    }
+
+    /// Clear all data in this table, see [`Table::clear`].
+    pub fn clear(&mut self) -> Result<(), WalError<T>> {
+        self.transaction(|table| {
+            table.clear();
+            Ok(())
+        })
+    }
+
+    /// Roll the log into a new snapshot.
+    ///
+    /// The snapshot is written to a temporary file first and then renamed, so a crash leaves
+    /// either the old snapshot and log or the new snapshot behind. The new snapshot starts a
+    /// new generation of the log, so frames of the old log left behind by a crash before it
+    /// is truncated are skipped when the table is opened.
+    pub fn compact(&mut self) -> Result<(), WalError<T>> {
+        let generation = self.log.generation + 1;
+        let temporary = self.path.join(SNAPSHOT_TEMPORARY);
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        bincode::options().serialize_into(&mut writer, &generation)?;
+        bincode::options().serialize_into(&mut writer, &self.table)?;
+        let file = writer.into_inner().map_err(|error| error.into_error())?;
+        file.sync_all()?;
+        fs::rename(&temporary, self.path.join(SNAPSHOT))?;
+        self.log.generation = generation;
+
+        // make sure the rename is durable before dropping the log.
+        #[cfg(unix)]
+        File::open(&self.path)?.sync_all()?;
+
+        self.log.truncate(0)?;
+        Ok(())
+    }
+
+    /// Close the durable table, returning the in-memory table.
+    pub fn into_inner(self) -> Table<T, S> {
+        self.table
+    }
+}
+
+/// Apply a logged record to a table, without applying hooks.
+fn replay<T: Identity + Clone, S: Threading>(
+    table: &mut Table<T, S>,
+    record: Record<T, T::PrimaryKey>,
+) -> Result<(), TableError<T>> {
+    match record {
+        Record::Put(element) => {
+            table.upsert_unhooked(element)?;
+        }
+        Record::Delete(key) => {
+            table.remove_unhooked(&key)?;
+        }
+        Record::Clear => table.clear_unhooked()?,
+    }
+    Ok(())
+}
+
+impl<T: Identity, S: Threading> Deref for DurableTable<T, S> {
+    type Target = Table<T, S>;
+
+    fn deref(&self) -> &Table<T, S> {
+        &self.table
+    }
+}